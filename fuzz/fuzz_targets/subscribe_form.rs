@@ -0,0 +1,40 @@
+//! Deserialise arbitrary bytes as the `application/x-www-form-urlencoded` body of
+//! `POST /subscriptions` and validate them, as `ValidatedForm` does.
+#![no_main]
+use actix_web::{test::TestRequest, web};
+use libfuzzer_sys::fuzz_target;
+use zero2prod::{
+    configuration::{CustomFieldConfigurations, CustomFieldType, SubscriptionsConfigurations},
+    domain::NewSubscriber,
+    routes::FormData,
+    validation::Validate,
+};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(form) = serde_urlencoded::from_bytes::<FormData>(data) {
+        // Custom fields of every type, so that their parsing gets exercised too
+        let subscriptions = SubscriptionsConfigurations {
+            custom_fields: vec![
+                CustomFieldConfigurations {
+                    name: "company".into(),
+                    kind: CustomFieldType::String,
+                    required: true,
+                },
+                CustomFieldConfigurations {
+                    name: "age".into(),
+                    kind: CustomFieldType::Number,
+                    required: false,
+                },
+                CustomFieldConfigurations {
+                    name: "beta".into(),
+                    kind: CustomFieldType::Boolean,
+                    required: false,
+                },
+            ],
+        };
+        let request = TestRequest::default()
+            .app_data(web::Data::new(subscriptions))
+            .to_http_request();
+        let _ = NewSubscriber::validate(form, &request);
+    }
+});
@@ -0,0 +1,13 @@
+//! Deserialise arbitrary bytes as a JSON subscribe payload and validate them, as
+//! `ValidatedJson` does.
+#![no_main]
+use actix_web::test::TestRequest;
+use libfuzzer_sys::fuzz_target;
+use zero2prod::{domain::NewSubscriber, routes::FormData, validation::Validate};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(payload) = serde_json::from_slice::<FormData>(data) {
+        let request = TestRequest::default().to_http_request();
+        let _ = NewSubscriber::validate(payload, &request);
+    }
+});
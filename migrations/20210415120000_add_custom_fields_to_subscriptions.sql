@@ -0,0 +1,3 @@
+-- Add Custom Fields Column
+-- Free-form subscriber data whose shape is defined in configuration
+ALTER TABLE subscriptions ADD COLUMN custom_fields JSONB NOT NULL DEFAULT '{}'::jsonb;
@@ -0,0 +1,9 @@
+-- Add Attribution Columns
+-- Where a subscriber came from: referral code and UTM parameters of the sign-up
+ALTER TABLE subscriptions
+    ADD COLUMN referrer TEXT NULL,
+    ADD COLUMN utm_source TEXT NULL,
+    ADD COLUMN utm_medium TEXT NULL,
+    ADD COLUMN utm_campaign TEXT NULL,
+    ADD COLUMN utm_term TEXT NULL,
+    ADD COLUMN utm_content TEXT NULL;
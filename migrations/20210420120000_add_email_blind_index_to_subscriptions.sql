@@ -0,0 +1,9 @@
+-- Add Email Blind Index Column
+-- When subscribers' data is encrypted, the email column holds a randomised
+-- ciphertext, so uniqueness is enforced on a keyed hash of the plaintext instead
+ALTER TABLE subscriptions ADD COLUMN email_blind_index TEXT NULL UNIQUE;
+-- Add Encryption Key Id Column
+-- Id of the key the email and name of a subscriber are encrypted with, NULL when
+-- they're stored in plaintext. Names can contain anything, so the shape of a
+-- stored value isn't enough to tell ciphertext and plaintext apart
+ALTER TABLE subscriptions ADD COLUMN encryption_key_id TEXT NULL;
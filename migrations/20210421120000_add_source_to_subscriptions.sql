@@ -0,0 +1,5 @@
+-- Add Source Column
+-- Channel the subscription came through, see domain::SubscriptionSource
+ALTER TABLE subscriptions
+    ADD COLUMN source TEXT NULL
+    CHECK (source IN ('api', 'widget', 'import', 'manual'));
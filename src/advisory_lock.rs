@@ -0,0 +1,51 @@
+use std::future::Future;
+
+use sqlx::{Connection, PgPool};
+
+use crate::db;
+
+/// Postgres advisory lock keys of the jobs that must run on a single replica at a time.
+pub mod keys {
+    pub const REENCRYPT_SUBSCRIBERS: i64 = 1;
+}
+
+#[derive(sqlx::FromRow)]
+struct Lock {
+    // Postgres can't tell whether the result of a function is nullable
+    acquired: Option<bool>,
+}
+
+/// Run `job` unless another replica is already running the job identified by `key`.
+/// Returns `None` when the job has been skipped.
+///
+/// Leadership is based on a session-level Postgres advisory lock, held by a
+/// dedicated connection for the whole duration of the job. Postgres releases the
+/// lock as soon as that session ends, so if the replica running the job dies,
+/// another one can take over the next time it tries.
+#[tracing::instrument(name = "Running singleton job", skip(pool, job))]
+pub async fn run_exclusively<F, Fut, T>(
+    pool: &PgPool,
+    key: i64,
+    job: F,
+) -> Result<Option<T>, sqlx::Error>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<T, sqlx::Error>>,
+{
+    // Detached from the pool: closing the connection at the end is the most reliable
+    // way to release the lock, even if the job fails
+    let mut connection = pool.acquire().await?.detach();
+    let lock = db::query_as!(Lock, "SELECT pg_try_advisory_lock($1) AS acquired", key)
+        .fetch_one(&mut connection)
+        .await?;
+
+    if lock.acquired != Some(true) {
+        tracing::info!("The job is already running on another replica, skipping it");
+        connection.close().await?;
+        return Ok(None);
+    }
+
+    let result = job().await;
+    connection.close().await?;
+    result.map(Some)
+}
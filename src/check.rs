@@ -0,0 +1,140 @@
+use std::{collections::HashMap, io::Error};
+
+use sqlx::PgPool;
+
+use crate::{
+    configuration::get_configurations, encryption::PiiCipher, startup::get_connection_pool,
+};
+
+/// Outcome of the preflight checks run by `zero2prod check`.
+#[derive(Default)]
+struct Report {
+    failures: usize,
+}
+
+impl Report {
+    fn record(&mut self, check: &str, outcome: Result<String, String>) -> bool {
+        match outcome {
+            Ok(details) => {
+                println!("[ OK ] {}: {}", check, details);
+                true
+            }
+            Err(details) => {
+                println!("[FAIL] {}: {}", check, details);
+                self.failures += 1;
+                false
+            }
+        }
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        if self.failures == 0 {
+            Ok(())
+        } else {
+            Err(Error::other(format!("{} check(s) failed", self.failures)))
+        }
+    }
+}
+
+/// Entry point of `zero2prod check`, a deploy preflight.
+///
+/// It loads the configurations, connects to Postgres and verifies that every
+/// migration has been applied, printing a line per check. It fails if any of the
+/// checks does, so the process exits with a non-zero status.
+pub async fn check() -> Result<(), Error> {
+    let mut report = Report::default();
+
+    let configurations = match get_configurations() {
+        Ok(configurations) => {
+            report.record("Configurations", Ok("loaded".into()));
+            configurations
+        }
+        Err(e) => {
+            report.record("Configurations", Err(e.to_string()));
+            // Nothing else can be checked without them
+            return report.finish();
+        }
+    };
+
+    if configurations.telemetry.redaction_key.is_some() {
+        report.record(
+            "Redaction key",
+            configurations
+                .telemetry
+                .redaction_key()
+                .map(|_| "valid".into()),
+        );
+    }
+
+    if let Some(encryption) = &configurations.encryption {
+        report.record(
+            "Encryption keys",
+            PiiCipher::from_configurations(encryption)
+                .map(|_| format!("current key is {}", encryption.current_key_id)),
+        );
+    }
+
+    let pool = get_connection_pool(&configurations.database);
+    let database = format!(
+        "{}:{}/{}",
+        configurations.database.host,
+        configurations.database.port,
+        configurations.database.database_name
+    );
+    let connected = report.record(
+        "Database",
+        sqlx::query("SELECT 1")
+            .execute(&pool)
+            .await
+            .map(|_| format!("connected to {}", database))
+            .map_err(|e| format!("failed to connect to {}: {}", database, e)),
+    );
+    if connected {
+        report.record("Migrations", check_migrations(&pool).await);
+    }
+
+    report.finish()
+}
+
+/// Compare the migrations applied to the database with the ones embedded in the
+/// binary, reporting the pending ones and the ones that changed after being applied.
+pub async fn check_migrations(pool: &PgPool) -> Result<String, String> {
+    let migrator = sqlx::migrate!("./migrations");
+    // The bookkeeping table of sqlx, not part of our schema: we don't check it at
+    // compile time
+    let applied: HashMap<i64, Vec<u8>> =
+        sqlx::query_as("SELECT version, checksum FROM _sqlx_migrations WHERE success")
+            .fetch_all(pool)
+            .await
+            .map_err(|e| format!("failed to read the applied migrations: {}", e))?
+            .into_iter()
+            .collect();
+
+    let mut pending = Vec::new();
+    let mut modified = Vec::new();
+    for migration in migrator.migrations.iter() {
+        match applied.get(&migration.version) {
+            None => pending.push(migration.version.to_string()),
+            Some(checksum) if checksum[..] != migration.checksum[..] => {
+                modified.push(migration.version.to_string())
+            }
+            Some(_) => {}
+        }
+    }
+
+    if pending.is_empty() && modified.is_empty() {
+        Ok(format!("{} applied", migrator.migrations.len()))
+    } else {
+        let mut problems = Vec::new();
+        if !pending.is_empty() {
+            problems.push(format!("pending: {}", pending.join(", ")));
+        }
+        if !modified.is_empty() {
+            problems.push(format!(
+                "modified after being applied: {}",
+                modified.join(", ")
+            ));
+        }
+        Err(problems.join("; "))
+    }
+}
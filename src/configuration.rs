@@ -1,11 +1,16 @@
 use std::{
+    collections::{HashMap, HashSet},
     convert::{TryFrom, TryInto},
     env::current_dir,
+    net::IpAddr,
+    time::Duration,
 };
 
 use serde_aux::field_attributes::deserialize_number_from_string;
 use sqlx::postgres::PgConnectOptions;
 
+use crate::routes::RESERVED_FIELDS;
+
 /// Struct that models our app-level configurations.
 ///
 /// We have two grous of configuration to handle: `actix-web` server
@@ -15,6 +20,57 @@ use sqlx::postgres::PgConnectOptions;
 pub struct Configurations {
     pub database: DatabaseConfigurations,
     pub application: ApplicationConfigurations,
+    pub subscriptions: SubscriptionsConfigurations,
+    /// Encryption at rest of subscribers' personally identifiable data.
+    /// It's disabled when the section is missing.
+    pub encryption: Option<EncryptionConfigurations>,
+    #[serde(default)]
+    pub telemetry: TelemetryConfigurations,
+}
+
+impl Configurations {
+    /// Check the constraints between values that deserialisation alone can't enforce.
+    pub fn validate(&self) -> Result<(), String> {
+        self.application.load_shedding.validate()?;
+        self.subscriptions.validate()?;
+
+        Ok(())
+    }
+}
+
+/// Settings about what ends up in the logs.
+#[derive(serde::Deserialize)]
+pub struct TelemetryConfigurations {
+    /// Replace subscribers' personally identifiable data with hashes in logs.
+    /// Enabled unless explicitly turned off.
+    pub redact_pii: bool,
+    /// Base64-encoded key of the HMAC behind those hashes, at least 256 bits long.
+    /// Without it, every process picks a random one, so hashes can only be
+    /// correlated within the logs of a single instance.
+    pub redaction_key: Option<String>,
+}
+
+impl TelemetryConfigurations {
+    pub fn redaction_key(&self) -> Result<Option<Vec<u8>>, String> {
+        let key = match &self.redaction_key {
+            Some(key) => key,
+            None => return Ok(None),
+        };
+        let key = base64::decode(key).map_err(|_| "The redaction key is not base64.")?;
+        if key.len() < 32 {
+            return Err("The redaction key must be at least 256 bits long.".into());
+        }
+        Ok(Some(key))
+    }
+}
+
+impl Default for TelemetryConfigurations {
+    fn default() -> Self {
+        Self {
+            redact_pii: true,
+            redaction_key: None,
+        }
+    }
 }
 
 /// Configurable portion of the running application address.
@@ -31,6 +87,151 @@ pub struct ApplicationConfigurations {
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
     pub host: String,
+    pub server: ServerConfigurations,
+    pub timeouts: TimeoutsConfigurations,
+    pub load_shedding: LoadSheddingConfigurations,
+    /// IPs of the reverse proxies allowed to set the `Forwarded` and `X-Forwarded-*`
+    /// headers. They're ignored for any other peer.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+/// Tuning knobs for the `actix-web`'s [HttpServer](actix_web::HttpServer).
+///
+/// The defaults that work on a big VM are not the ones we want on a small container
+/// (and viceversa), so we expose them to be customised per environment.
+#[derive(serde::Deserialize)]
+pub struct ServerConfigurations {
+    /// Number of worker threads. If unset, `actix-web` starts one per physical core.
+    pub workers: Option<usize>,
+    /// Seconds to keep an idle connection open waiting for a new request.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub keep_alive: usize,
+    /// Milliseconds a client has to send the request head before getting a 408.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub client_request_timeout: u64,
+    /// Maximum number of pending connections waiting to be accepted.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub backlog: u32,
+}
+
+/// Maximum time, in milliseconds, each route is allowed to take before we give up
+/// on it and return `504 GATEWAY TIMEOUT`.
+#[derive(serde::Deserialize)]
+pub struct TimeoutsConfigurations {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub subscribe_milliseconds: u64,
+}
+
+impl TimeoutsConfigurations {
+    pub fn subscribe(&self) -> Duration {
+        Duration::from_millis(self.subscribe_milliseconds)
+    }
+}
+
+/// Requests processed concurrently before we start rejecting new ones with
+/// `503 SERVICE UNAVAILABLE`.
+///
+/// The limit adapts to the observed latency, between `min_in_flight` and
+/// `max_in_flight` (see [LoadShedding](crate::middleware::LoadShedding)). The latter
+/// should stay in the same order of magnitude as the database pool size: beyond
+/// that, requests just wait for a connection.
+#[derive(serde::Deserialize)]
+pub struct LoadSheddingConfigurations {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_in_flight: usize,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_in_flight: usize,
+    /// Milliseconds a request is expected to take when the server isn't saturated.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub target_latency_milliseconds: u64,
+}
+
+impl LoadSheddingConfigurations {
+    pub fn target_latency(&self) -> Duration {
+        Duration::from_millis(self.target_latency_milliseconds)
+    }
+
+    /// With a limit of 0 no request would get through, not even the ones that
+    /// would raise it again.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_in_flight == 0 || self.min_in_flight == 0 {
+            return Err("load_shedding.min_in_flight and max_in_flight must be at least 1.".into());
+        }
+        if self.min_in_flight > self.max_in_flight {
+            return Err(format!(
+                "load_shedding.min_in_flight ({}) can't be greater than max_in_flight ({}).",
+                self.min_in_flight, self.max_in_flight
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Settings about the data we collect from subscribers.
+#[derive(serde::Deserialize, Clone)]
+pub struct SubscriptionsConfigurations {
+    /// Extra fields accepted by the subscribe endpoint, on top of email and name.
+    pub custom_fields: Vec<CustomFieldConfigurations>,
+}
+
+impl SubscriptionsConfigurations {
+    /// Custom fields named after a built-in field (see [RESERVED_FIELDS]) would never
+    /// get a value, and duplicated ones would be ambiguous.
+    pub fn validate(&self) -> Result<(), String> {
+        let mut names = HashSet::new();
+        for field in &self.custom_fields {
+            if RESERVED_FIELDS.contains(&field.name.as_str()) {
+                return Err(format!(
+                    "Custom field {} is reserved, reserved names are: {}.",
+                    field.name,
+                    RESERVED_FIELDS.join(", ")
+                ));
+            }
+            if !names.insert(field.name.as_str()) {
+                return Err(format!("Custom field {} is defined twice.", field.name));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Definition of a custom field accepted by the subscribe endpoint.
+///
+/// Its name can't be one of the built-in fields ([RESERVED_FIELDS]): `email`,
+/// `name`, `source`, `ref` and the `utm_*` parameters.
+#[derive(serde::Deserialize, Clone)]
+pub struct CustomFieldConfigurations {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: CustomFieldType,
+    #[serde(default)]
+    pub required: bool,
+}
+
+/// Type the value of a custom field is validated and stored as.
+#[derive(serde::Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum CustomFieldType {
+    String,
+    Number,
+    Boolean,
+}
+
+/// Keys used to encrypt subscribers' email and name before storing them.
+///
+/// Keys are base64-encoded 256-bit values, indexed by an id that is stored next to
+/// every encrypted value. To rotate keys, add a new one, point `current_key_id` to
+/// it and keep the previous ones around until every row has been re-encrypted.
+#[derive(serde::Deserialize)]
+pub struct EncryptionConfigurations {
+    pub current_key_id: String,
+    pub keys: HashMap<String, String>,
+    /// Base64-encoded key of the HMAC used to look up encrypted emails, at least 256
+    /// bits long. It can't be rotated without recomputing every blind index.
+    pub blind_index_key: String,
 }
 
 #[derive(serde::Deserialize)]
@@ -124,5 +325,57 @@ pub fn get_configurations() -> Result<Configurations, config::ConfigError> {
 
     // Try to convert the configuration values it read into
     // our Configurations type
-    configurations.try_into()
+    let configurations: Configurations = configurations.try_into()?;
+    configurations
+        .validate()
+        .map_err(config::ConfigError::Message)?;
+
+    Ok(configurations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        CustomFieldConfigurations, CustomFieldType, LoadSheddingConfigurations,
+        SubscriptionsConfigurations,
+    };
+
+    fn load_shedding(min_in_flight: usize, max_in_flight: usize) -> LoadSheddingConfigurations {
+        LoadSheddingConfigurations {
+            min_in_flight,
+            max_in_flight,
+            target_latency_milliseconds: 250,
+        }
+    }
+
+    #[test]
+    fn load_shedding_limits_must_be_positive_and_ordered() {
+        assert!(load_shedding(8, 64).validate().is_ok());
+        assert!(load_shedding(1, 1).validate().is_ok());
+        assert!(load_shedding(0, 64).validate().is_err());
+        assert!(load_shedding(0, 0).validate().is_err());
+        assert!(load_shedding(64, 8).validate().is_err());
+    }
+
+    fn subscriptions(names: &[&str]) -> SubscriptionsConfigurations {
+        SubscriptionsConfigurations {
+            custom_fields: names
+                .iter()
+                .map(|name| CustomFieldConfigurations {
+                    name: name.to_string(),
+                    kind: CustomFieldType::String,
+                    required: true,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn custom_fields_must_have_unique_unreserved_names() {
+        assert!(subscriptions(&["company", "role"]).validate().is_ok());
+        assert!(subscriptions(&["company", "company"]).validate().is_err());
+        for reserved in &["email", "name", "source", "ref", "utm_campaign"] {
+            assert!(subscriptions(&[reserved]).validate().is_err());
+        }
+    }
 }
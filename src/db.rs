@@ -0,0 +1,81 @@
+//! Wrappers around sqlx's query macros.
+//!
+//! With the `checked-queries` feature (enabled by default), they expand to
+//! [sqlx::query!] and [sqlx::query_as!]: queries are checked against a live
+//! database, or against `sqlx-data.json` when `SQLX_OFFLINE=true`, at compile time.
+//!
+//! Without it, they fall back to the runtime-checked [sqlx::query()] and
+//! [sqlx::query_as()], binding the arguments in order. That lets the crate compile
+//! without Postgres nor an up to date `sqlx-data.json`:
+//!
+//! ```sh
+//! cargo build --no-default-features
+//! ```
+//!
+//! Mistakes in a query then only surface at runtime, so CI keeps building with
+//! the feature enabled.
+
+use actix_web::rt::time::timeout;
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+
+use crate::middleware::Deadline;
+
+/// Same as [sqlx::query!], for queries whose output (if any) isn't read by
+/// column name.
+macro_rules! query {
+    ($sql:literal $(, $arg:expr)* $(,)?) => {{
+        #[cfg(feature = "checked-queries")]
+        {
+            sqlx::query!($sql $(, $arg)*)
+        }
+        #[cfg(not(feature = "checked-queries"))]
+        {
+            sqlx::query($sql)$(.bind(&$arg))*
+        }
+    }};
+}
+
+/// Same as [sqlx::query_as!]. `$out` must also implement [sqlx::FromRow] for the
+/// runtime-checked fallback.
+macro_rules! query_as {
+    ($out:path, $sql:literal $(, $arg:expr)* $(,)?) => {{
+        #[cfg(feature = "checked-queries")]
+        {
+            sqlx::query_as!($out, $sql $(, $arg)*)
+        }
+        #[cfg(not(feature = "checked-queries"))]
+        {
+            sqlx::query_as::<_, $out>($sql)$(.bind(&$arg))*
+        }
+    }};
+}
+
+pub(crate) use query;
+pub(crate) use query_as;
+
+/// Begin a transaction that can't outlive `deadline`.
+///
+/// Waiting for a connection gives up when the deadline is hit, and so does
+/// Postgres with the statements of the transaction (through a local
+/// `statement_timeout`). That way, when the caller has already given up on a
+/// request, neither the application nor the database keep working on it.
+pub async fn begin_with_deadline(
+    pool: &PgPool,
+    deadline: Deadline,
+) -> Result<Transaction<'static, Postgres>, sqlx::Error> {
+    let mut transaction = timeout(deadline.remaining(), pool.begin())
+        .await
+        .map_err(|_| sqlx::Error::PoolTimedOut)??;
+
+    // A statement_timeout of 0 disables it
+    let remaining = deadline.remaining().as_millis();
+    if remaining == 0 {
+        return Err(sqlx::Error::PoolTimedOut);
+    }
+    // SET doesn't support bind parameters
+    transaction
+        .execute(&*format!("SET LOCAL statement_timeout = {}", remaining))
+        .await?;
+
+    Ok(transaction)
+}
@@ -0,0 +1,57 @@
+/// Where a subscriber came from: the referral code and the UTM parameters attached
+/// to the sign-up.
+///
+/// Attribution is best-effort: invalid values are dropped instead of failing the
+/// subscription, as losing a subscriber over a malformed tracking parameter is not
+/// a good trade-off.
+#[derive(Debug, Default)]
+pub struct Attribution {
+    pub referrer: Option<String>,
+    pub utm_source: Option<String>,
+    pub utm_medium: Option<String>,
+    pub utm_campaign: Option<String>,
+    pub utm_term: Option<String>,
+    pub utm_content: Option<String>,
+}
+
+impl Attribution {
+    /// Maximum length of each value, measured in characters.
+    const MAX_LENGTH: usize = 256;
+
+    /// Normalise a raw attribution value: blank values are discarded and the rest
+    /// are trimmed and truncated to [Attribution::MAX_LENGTH].
+    pub fn parse_value(value: Option<String>) -> Option<String> {
+        value
+            .map(|value| {
+                value
+                    .trim()
+                    .chars()
+                    .take(Self::MAX_LENGTH)
+                    .collect::<String>()
+            })
+            .filter(|value| !value.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Attribution;
+
+    #[test]
+    fn blank_values_are_discarded() {
+        assert_eq!(Attribution::parse_value(Some("  ".into())), None);
+        assert_eq!(Attribution::parse_value(None), None);
+    }
+
+    #[test]
+    fn values_are_trimmed_and_truncated() {
+        assert_eq!(
+            Attribution::parse_value(Some(" twitter ".into())),
+            Some("twitter".into())
+        );
+        assert_eq!(
+            Attribution::parse_value(Some("a".repeat(300))).map(|v| v.len()),
+            Some(256)
+        );
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+
+use serde_json::{Map, Number, Value};
+
+use crate::configuration::{CustomFieldConfigurations, CustomFieldType};
+
+/// Operator-defined subscriber data (e. g., company or referral source), validated
+/// against the definitions found in configuration.
+///
+/// Fields that are not defined in configuration are ignored: forms often carry extra
+/// inputs (submit buttons, honeypots, etc.) that we don't want to store.
+#[derive(Debug, Default)]
+pub struct CustomFields(Map<String, Value>);
+
+impl CustomFields {
+    /// Returns an instance of [CustomFields] if every defined field satisfies its
+    /// definition, the list of `(field, error message)` failures otherwise.
+    pub fn parse(
+        mut raw: HashMap<String, String>,
+        definitions: &[CustomFieldConfigurations],
+    ) -> Result<CustomFields, Vec<(String, String)>> {
+        let mut fields = Map::new();
+        let mut errors = Vec::new();
+
+        for definition in definitions {
+            // HTML forms submit blank inputs as empty strings
+            let value = raw
+                .remove(&definition.name)
+                .filter(|value| !value.trim().is_empty());
+
+            match (value, definition.required) {
+                (Some(value), _) => match parse_value(&value, definition.kind) {
+                    Ok(value) => {
+                        fields.insert(definition.name.clone(), value);
+                    }
+                    Err(e) => errors.push((definition.name.clone(), e)),
+                },
+                (None, true) => errors.push((
+                    definition.name.clone(),
+                    format!("{} is required.", definition.name),
+                )),
+                (None, false) => {}
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(Self(fields))
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// JSON object to be stored in the `custom_fields` column.
+    pub fn to_json(&self) -> Value {
+        Value::Object(self.0.clone())
+    }
+}
+
+fn parse_value(value: &str, kind: CustomFieldType) -> Result<Value, String> {
+    match kind {
+        CustomFieldType::String => Ok(Value::String(value.to_owned())),
+        CustomFieldType::Number => parse_number(value.trim())
+            .map(Value::Number)
+            .ok_or_else(|| format!("{} is not a valid number.", value)),
+        CustomFieldType::Boolean => match value.trim() {
+            "true" => Ok(Value::Bool(true)),
+            "false" => Ok(Value::Bool(false)),
+            _ => Err(format!("{} is not a valid boolean.", value)),
+        },
+    }
+}
+
+/// Integers are kept as such: going through `f64` would turn `12` into `12.0` and
+/// lose precision past 2^53.
+fn parse_number(value: &str) -> Option<Number> {
+    value
+        .parse::<i64>()
+        .map(Number::from)
+        .or_else(|_| value.parse::<u64>().map(Number::from))
+        .ok()
+        .or_else(|| value.parse::<f64>().ok().and_then(Number::from_f64))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde_json::json;
+
+    use super::CustomFields;
+    use crate::configuration::{CustomFieldConfigurations, CustomFieldType};
+
+    fn definition(name: &str, kind: CustomFieldType, required: bool) -> CustomFieldConfigurations {
+        CustomFieldConfigurations {
+            name: name.into(),
+            kind,
+            required,
+        }
+    }
+
+    fn raw(fields: &[(&str, &str)]) -> HashMap<String, String> {
+        fields
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn values_are_converted_to_their_defined_type() {
+        let definitions = vec![
+            definition("company", CustomFieldType::String, false),
+            definition("employees", CustomFieldType::Number, false),
+            definition("customer", CustomFieldType::Boolean, false),
+        ];
+        let fields = raw(&[
+            ("company", "Bourbaki"),
+            ("employees", "12"),
+            ("customer", "true"),
+        ]);
+
+        let custom_fields = CustomFields::parse(fields, &definitions).unwrap();
+
+        assert_eq!(
+            custom_fields.to_json(),
+            json!({ "company": "Bourbaki", "employees": 12, "customer": true })
+        );
+    }
+
+    #[test]
+    fn numbers_keep_their_precision() {
+        let definitions = vec![definition("count", CustomFieldType::Number, false)];
+
+        for (value, expected) in [
+            ("-12", json!(-12)),
+            ("9007199254740993", json!(9007199254740993_i64)),
+            ("18446744073709551615", json!(u64::MAX)),
+            ("1.5", json!(1.5)),
+        ] {
+            let custom_fields =
+                CustomFields::parse(raw(&[("count", value)]), &definitions).unwrap();
+            assert_eq!(custom_fields.to_json(), json!({ "count": expected }));
+        }
+    }
+
+    #[test]
+    fn undefined_fields_are_ignored() {
+        let fields = raw(&[("submit", "Subscribe")]);
+
+        let custom_fields = CustomFields::parse(fields, &[]).unwrap();
+
+        assert_eq!(custom_fields.to_json(), json!({}));
+    }
+
+    #[test]
+    fn missing_or_blank_required_fields_are_rejected() {
+        let definitions = vec![definition("company", CustomFieldType::String, true)];
+
+        for fields in [raw(&[]), raw(&[("company", " ")])] {
+            let errors = CustomFields::parse(fields, &definitions).unwrap_err();
+            assert_eq!(errors[0].0, "company");
+        }
+    }
+
+    #[test]
+    fn values_not_matching_their_type_are_rejected() {
+        let definitions = vec![
+            definition("employees", CustomFieldType::Number, false),
+            definition("customer", CustomFieldType::Boolean, false),
+        ];
+        let fields = raw(&[("employees", "a dozen"), ("customer", "maybe")]);
+
+        let errors = CustomFields::parse(fields, &definitions).unwrap_err();
+
+        assert_eq!(errors.len(), 2);
+    }
+}
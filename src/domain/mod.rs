@@ -0,0 +1,15 @@
+mod attribution;
+mod custom_fields;
+mod new_subscriber;
+mod subscriber_email;
+mod subscriber_name;
+mod subscription_source;
+#[cfg(feature = "testing")]
+pub mod testing;
+
+pub use attribution::Attribution;
+pub use custom_fields::CustomFields;
+pub use new_subscriber::NewSubscriber;
+pub use subscriber_email::SubscriberEmail;
+pub use subscriber_name::SubscriberName;
+pub use subscription_source::SubscriptionSource;
@@ -0,0 +1,16 @@
+use crate::domain::{
+    Attribution, CustomFields, SubscriberEmail, SubscriberName, SubscriptionSource,
+};
+
+/// A subscriber whose data has already been validated.
+///
+/// Handlers and database functions only deal with [NewSubscriber], so it's
+/// impossible to persist data that hasn't gone through the parsing functions.
+pub struct NewSubscriber {
+    pub email: SubscriberEmail,
+    pub name: SubscriberName,
+    pub custom_fields: CustomFields,
+    pub attribution: Attribution,
+    /// Unknown when the caller didn't tell us.
+    pub source: Option<SubscriptionSource>,
+}
@@ -0,0 +1,55 @@
+use validator::validate_email;
+
+/// Email of a subscriber, guaranteed to be a syntactically valid address.
+///
+/// As with [SubscriberName](crate::domain::SubscriberName), the only way to get
+/// an instance is through [SubscriberEmail::parse].
+#[derive(Debug)]
+pub struct SubscriberEmail(String);
+
+impl SubscriberEmail {
+    /// Returns an instance of [SubscriberEmail] if the input is a valid email
+    /// address, an error message otherwise.
+    pub fn parse(s: String) -> Result<SubscriberEmail, String> {
+        if validate_email(&s) {
+            Ok(Self(s))
+        } else {
+            Err(format!("{} is not a valid email address.", s))
+        }
+    }
+}
+
+impl AsRef<str> for SubscriberEmail {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriberEmail;
+
+    #[test]
+    fn empty_string_is_rejected() {
+        let email = "".to_string();
+        assert!(SubscriberEmail::parse(email).is_err());
+    }
+
+    #[test]
+    fn email_missing_at_symbol_is_rejected() {
+        let email = "nick_bourbaki.com".to_string();
+        assert!(SubscriberEmail::parse(email).is_err());
+    }
+
+    #[test]
+    fn email_missing_subject_is_rejected() {
+        let email = "@gmail.com".to_string();
+        assert!(SubscriberEmail::parse(email).is_err());
+    }
+
+    #[test]
+    fn a_valid_email_is_parsed_successfully() {
+        let email = "nick_bourbaki@gmail.com".to_string();
+        assert!(SubscriberEmail::parse(email).is_ok());
+    }
+}
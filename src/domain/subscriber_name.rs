@@ -0,0 +1,92 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Name of a subscriber, guaranteed to satisfy our validation constraints.
+///
+/// The inner field is private, so the only way to build a [SubscriberName] is
+/// through [SubscriberName::parse] (_parse, don't validate_).
+#[derive(Debug)]
+pub struct SubscriberName(String);
+
+impl SubscriberName {
+    /// Maximum length of a name, measured in graphemes.
+    const MAX_LENGTH: usize = 256;
+    pub(crate) const FORBIDDEN_CHARACTERS: [char; 9] =
+        ['/', '(', ')', '"', '<', '>', '\\', '{', '}'];
+
+    /// Returns an instance of [SubscriberName] if the input satisfies all our
+    /// validation constraints on subscriber names, an error message otherwise.
+    pub fn parse(s: String) -> Result<SubscriberName, String> {
+        if s.trim().is_empty() {
+            return Err("Name cannot be empty.".into());
+        }
+
+        // A grapheme is defined by the Unicode standard as a "user-perceived"
+        // character: `å` is a single grapheme, but it is composed of two characters
+        // (`a` and `̊`). `graphemes()` returns an iterator over them; `true` asks
+        // for the extended grapheme definition set, the recommended one.
+        if s.graphemes(true).count() > Self::MAX_LENGTH {
+            return Err(format!(
+                "Name cannot be longer than {} characters.",
+                Self::MAX_LENGTH
+            ));
+        }
+
+        if s.chars().any(|c| Self::FORBIDDEN_CHARACTERS.contains(&c)) {
+            return Err(format!(
+                "Name cannot contain any of {:?}.",
+                Self::FORBIDDEN_CHARACTERS
+            ));
+        }
+
+        Ok(Self(s))
+    }
+}
+
+impl AsRef<str> for SubscriberName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriberName;
+
+    #[test]
+    fn a_256_grapheme_long_name_is_valid() {
+        let name = "ё".repeat(256);
+        assert!(SubscriberName::parse(name).is_ok());
+    }
+
+    #[test]
+    fn a_name_longer_than_256_graphemes_is_rejected() {
+        let name = "a".repeat(257);
+        assert!(SubscriberName::parse(name).is_err());
+    }
+
+    #[test]
+    fn whitespace_only_names_are_rejected() {
+        let name = " ".to_string();
+        assert!(SubscriberName::parse(name).is_err());
+    }
+
+    #[test]
+    fn empty_string_is_rejected() {
+        let name = "".to_string();
+        assert!(SubscriberName::parse(name).is_err());
+    }
+
+    #[test]
+    fn names_containing_an_invalid_character_are_rejected() {
+        for name in &['/', '(', ')', '"', '<', '>', '\\', '{', '}'] {
+            let name = name.to_string();
+            assert!(SubscriberName::parse(name).is_err());
+        }
+    }
+
+    #[test]
+    fn a_valid_name_is_parsed_successfully() {
+        let name = "Nicolas Bourbaki".to_string();
+        assert!(SubscriberName::parse(name).is_ok());
+    }
+}
@@ -0,0 +1,66 @@
+/// Channel a subscription came through, to tell organic sign-ups from imported
+/// contacts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SubscriptionSource {
+    Api,
+    Widget,
+    Import,
+    Manual,
+}
+
+impl SubscriptionSource {
+    /// Every source, e. g., to generate fake subscribers.
+    pub const ALL: [SubscriptionSource; 4] = [
+        SubscriptionSource::Api,
+        SubscriptionSource::Widget,
+        SubscriptionSource::Import,
+        SubscriptionSource::Manual,
+    ];
+
+    /// Returns the [SubscriptionSource] named by the input, an error message
+    /// otherwise. Surrounding whitespace and case are ignored.
+    pub fn parse(s: &str) -> Result<SubscriptionSource, String> {
+        let s = s.trim().to_lowercase();
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|source| source.as_str() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(|source| source.as_str()).collect();
+                format!("Source must be one of {}.", names.join(", "))
+            })
+    }
+
+    /// Name of the source, as stored in the database.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriptionSource::Api => "api",
+            SubscriptionSource::Widget => "widget",
+            SubscriptionSource::Import => "import",
+            SubscriptionSource::Manual => "manual",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriptionSource;
+
+    #[test]
+    fn known_sources_are_parsed_successfully() {
+        assert_eq!(
+            SubscriptionSource::parse("widget"),
+            Ok(SubscriptionSource::Widget)
+        );
+        assert_eq!(
+            SubscriptionSource::parse(" Import "),
+            Ok(SubscriptionSource::Import)
+        );
+    }
+
+    #[test]
+    fn unknown_sources_are_rejected() {
+        assert!(SubscriptionSource::parse("newsletter").is_err());
+        assert!(SubscriptionSource::parse("").is_err());
+    }
+}
@@ -0,0 +1,108 @@
+//! [proptest] strategies for the domain types, only available with the `testing`
+//! feature.
+//!
+//! `valid_*` strategies generate domain types that went through their parsing
+//! function, while `invalid_*` ones generate raw inputs that the parsing function
+//! must reject. Both are meant to be shared by our tests and downstream ones, so
+//! that everybody agrees on what a valid input looks like.
+//!
+//! ```ignore
+//! use proptest::prelude::*;
+//! use zero2prod::domain::{testing::invalid_subscriber_email, SubscriberEmail};
+//!
+//! proptest! {
+//!     #[test]
+//!     fn invalid_emails_are_rejected(email in invalid_subscriber_email()) {
+//!         prop_assert!(SubscriberEmail::parse(email).is_err());
+//!     }
+//! }
+//! ```
+use proptest::prelude::*;
+
+use super::{SubscriberEmail, SubscriberName};
+
+/// Names made of letters, spaces and the punctuation commonly found in names.
+pub fn valid_subscriber_name() -> impl Strategy<Value = SubscriberName> {
+    "[\\p{L}][\\p{L} .,'-]{0,63}"
+        .prop_map(|name| SubscriberName::parse(name).expect("The generated name should be valid"))
+}
+
+/// Empty, whitespace-only, too long names and names with forbidden characters.
+pub fn invalid_subscriber_name() -> impl Strategy<Value = String> {
+    let forbidden_character =
+        proptest::sample::select(SubscriberName::FORBIDDEN_CHARACTERS.to_vec());
+    prop_oneof![
+        Just(String::new()),
+        "[ \t\r\n]{1,16}",
+        // Longer than SubscriberName::MAX_LENGTH. ASCII letters never merge into
+        // a single grapheme, unlike some Unicode ones (e. g., Hangul jamo)
+        "[a-zA-Z]{257,512}",
+        ("\\p{L}{0,16}", forbidden_character, "\\p{L}{0,16}")
+            .prop_map(|(head, c, tail)| format!("{}{}{}", head, c, tail)),
+    ]
+}
+
+/// Addresses like `first.last@example.com`.
+pub fn valid_subscriber_email() -> impl Strategy<Value = SubscriberEmail> {
+    "[a-z0-9]{1,16}([._+-][a-z0-9]{1,8})?@[a-z0-9]{1,16}\\.(com|org|net|io)".prop_map(|email| {
+        SubscriberEmail::parse(email).expect("The generated email should be valid")
+    })
+}
+
+/// Empty strings and addresses missing the `@`, the subject or the domain.
+pub fn invalid_subscriber_email() -> impl Strategy<Value = String> {
+    prop_oneof![
+        Just(String::new()),
+        "[a-z0-9]{1,16}\\.(com|org|net|io)",
+        "@[a-z0-9]{1,16}\\.(com|org|net|io)",
+        "[a-z0-9]{1,16}@",
+    ]
+}
+
+impl Arbitrary for SubscriberName {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        valid_subscriber_name().boxed()
+    }
+}
+
+impl Arbitrary for SubscriberEmail {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+        valid_subscriber_email().boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::{invalid_subscriber_email, invalid_subscriber_name};
+    use crate::domain::{SubscriberEmail, SubscriberName};
+
+    proptest! {
+        #[test]
+        fn valid_names_are_parsed_successfully(name in any::<SubscriberName>()) {
+            prop_assert!(SubscriberName::parse(name.as_ref().to_owned()).is_ok());
+        }
+
+        #[test]
+        fn invalid_names_are_rejected(name in invalid_subscriber_name()) {
+            prop_assert!(SubscriberName::parse(name).is_err());
+        }
+
+        #[test]
+        fn valid_emails_are_parsed_successfully(email in any::<SubscriberEmail>()) {
+            prop_assert!(SubscriberEmail::parse(email.as_ref().to_owned()).is_ok());
+        }
+
+        #[test]
+        fn invalid_emails_are_rejected(email in invalid_subscriber_email()) {
+            prop_assert!(SubscriberEmail::parse(email).is_err());
+        }
+    }
+}
@@ -0,0 +1,510 @@
+use std::collections::HashMap;
+
+use aes_gcm::{
+    aead::{Aead, NewAead, Payload},
+    Aes256Gcm, Nonce,
+};
+use hmac::{Hmac, Mac, NewMac};
+use rand::RngCore;
+use sha2::Sha256;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{configuration::EncryptionConfigurations, db};
+
+/// Prefix of the values encrypted by [PiiCipher].
+const PREFIX: &str = "enc:v1:";
+/// AES-GCM uses 96-bit nonces.
+const NONCE_LENGTH: usize = 12;
+/// Minimum length, in bytes, of the blind index key. A short key would make blind
+/// indexes as easy to reverse as plain hashes of the emails.
+const MIN_BLIND_INDEX_KEY_LENGTH: usize = 32;
+
+/// Application-level encryption for subscribers' personally identifiable data.
+///
+/// Values are encrypted with AES-256-GCM and stored as
+/// `enc:v1:<key id>:<base64(nonce + ciphertext)>`. Keeping the key id next to the
+/// ciphertext lets us rotate keys: new values are always encrypted with the current
+/// key, while old values can still be decrypted as long as their key is configured.
+/// See [reencrypt_subscribers] to migrate old values to the current key.
+///
+/// The key id is also stored in the `encryption_key_id` column, which is the only
+/// reliable way to tell encrypted rows apart: a name typed by a subscriber can
+/// look exactly like a ciphertext.
+///
+/// Each value is bound to the row and the column it's stored in (they're
+/// authenticated as associated data), so whoever can write to the database can't
+/// swap ciphertexts between columns or subscribers without decryption failing.
+///
+/// Encryption is randomised (a new nonce per value), so we can't look up or enforce
+/// uniqueness on the ciphertext. For that, we store a _blind index_ alongside it:
+/// a keyed hash (HMAC-SHA256) of the plaintext.
+pub struct PiiCipher {
+    current_key_id: String,
+    keys: HashMap<String, Aes256Gcm>,
+    blind_index_key: Vec<u8>,
+}
+
+/// Column of `subscriptions` holding an encrypted value.
+#[derive(Debug, Clone, Copy)]
+pub enum PiiColumn {
+    Email,
+    Name,
+}
+
+impl PiiColumn {
+    fn as_str(self) -> &'static str {
+        match self {
+            PiiColumn::Email => "email",
+            PiiColumn::Name => "name",
+        }
+    }
+}
+
+/// Failure while decrypting a stored value.
+#[derive(Debug)]
+pub enum DecryptionError {
+    Malformed,
+    UnknownKey(String),
+    InvalidCiphertext,
+}
+
+impl std::fmt::Display for DecryptionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecryptionError::Malformed => write!(f, "The encrypted value is malformed."),
+            DecryptionError::UnknownKey(id) => write!(f, "Key {} is not configured.", id),
+            DecryptionError::InvalidCiphertext => {
+                write!(f, "The encrypted value could not be authenticated.")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecryptionError {}
+
+impl PiiCipher {
+    /// Build a [PiiCipher] out of base64-encoded keys of (at least) 256 bits.
+    pub fn from_configurations(configurations: &EncryptionConfigurations) -> Result<Self, String> {
+        let mut keys = HashMap::new();
+        for (id, key) in &configurations.keys {
+            // Key ids are stored within the encrypted values, separated by ':'
+            if !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+                return Err(format!(
+                    "Key id {} can only contain alphanumeric characters and '-'.",
+                    id
+                ));
+            }
+            let key = base64::decode(key).map_err(|_| format!("Key {} is not base64.", id))?;
+            let cipher = Aes256Gcm::new_from_slice(&key)
+                .map_err(|_| format!("Key {} must be 256 bits long.", id))?;
+            keys.insert(id.clone(), cipher);
+        }
+
+        if !keys.contains_key(&configurations.current_key_id) {
+            return Err(format!(
+                "Current key {} is not configured.",
+                configurations.current_key_id
+            ));
+        }
+
+        let blind_index_key = base64::decode(&configurations.blind_index_key)
+            .map_err(|_| "The blind index key is not base64.".to_string())?;
+        if blind_index_key.len() < MIN_BLIND_INDEX_KEY_LENGTH {
+            return Err("The blind index key must be at least 256 bits long.".into());
+        }
+
+        Ok(Self {
+            current_key_id: configurations.current_key_id.clone(),
+            keys,
+            blind_index_key,
+        })
+    }
+
+    /// Encrypt `plaintext` with the current key, for the `column` of subscriber
+    /// `id`.
+    pub fn encrypt(&self, plaintext: &str, id: Uuid, column: PiiColumn) -> String {
+        let cipher = &self.keys[&self.current_key_id];
+        let mut nonce = [0u8; NONCE_LENGTH];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext.as_bytes(),
+                    aad: &associated_data(id, column),
+                },
+            )
+            .expect("AES-GCM encryption does not fail for in-memory buffers");
+
+        let mut payload = nonce.to_vec();
+        payload.extend(ciphertext);
+        format!(
+            "{}{}:{}",
+            PREFIX,
+            self.current_key_id,
+            base64::encode(payload)
+        )
+    }
+
+    /// Decrypt a value produced by [PiiCipher::encrypt] with any of the configured
+    /// keys. `id` and `column` must be the ones it was encrypted for.
+    pub fn decrypt(
+        &self,
+        stored: &str,
+        id: Uuid,
+        column: PiiColumn,
+    ) -> Result<String, DecryptionError> {
+        let encrypted = stored
+            .strip_prefix(PREFIX)
+            .ok_or(DecryptionError::Malformed)?;
+        let (key_id, payload) = encrypted
+            .split_once(':')
+            .ok_or(DecryptionError::Malformed)?;
+        let cipher = self
+            .keys
+            .get(key_id)
+            .ok_or_else(|| DecryptionError::UnknownKey(key_id.to_owned()))?;
+        let payload = base64::decode(payload).map_err(|_| DecryptionError::Malformed)?;
+        if payload.len() < NONCE_LENGTH {
+            return Err(DecryptionError::Malformed);
+        }
+
+        let (nonce, ciphertext) = payload.split_at(NONCE_LENGTH);
+        let plaintext = cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: &associated_data(id, column),
+                },
+            )
+            .map_err(|_| DecryptionError::InvalidCiphertext)?;
+        String::from_utf8(plaintext).map_err(|_| DecryptionError::InvalidCiphertext)
+    }
+
+    /// Deterministic keyed hash of `plaintext`, used to look up and enforce the
+    /// uniqueness of encrypted values.
+    pub fn blind_index(&self, plaintext: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.blind_index_key)
+            .expect("HMAC accepts keys of any length");
+        mac.update(plaintext.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    /// Id of the key new values are encrypted with.
+    pub fn current_key_id(&self) -> &str {
+        &self.current_key_id
+    }
+}
+
+/// Where an encrypted value belongs: `<column>:<subscriber id>`.
+fn associated_data(id: Uuid, column: PiiColumn) -> Vec<u8> {
+    format!("{}:{}", column.as_str(), id).into_bytes()
+}
+
+#[derive(sqlx::FromRow)]
+struct StoredSubscriber {
+    id: Uuid,
+    email: String,
+    name: String,
+    encryption_key_id: Option<String>,
+}
+
+impl StoredSubscriber {
+    /// Email and name of the subscriber in plaintext.
+    fn decrypt(&self, cipher: &PiiCipher) -> Result<(String, String), DecryptionError> {
+        match self.encryption_key_id {
+            Some(_) => Ok((
+                cipher.decrypt(&self.email, self.id, PiiColumn::Email)?,
+                cipher.decrypt(&self.name, self.id, PiiColumn::Name)?,
+            )),
+            None => Ok((self.email.clone(), self.name.clone())),
+        }
+    }
+}
+
+/// Re-encrypt, with the current key, the subscribers stored in plaintext or
+/// encrypted with a previous key. Returns the number of updated subscribers.
+///
+/// It's idempotent and works in batches, so it can be safely interrupted and
+/// re-run (e. g., on every startup) until every row is up to date. Rows that can't
+/// be decrypted (e. g., because their key is no longer configured) or that duplicate
+/// the email of another row are logged and skipped, so they don't hold back the
+/// others. Once it reports 0 updates and no errors, previous keys can be removed
+/// from configuration.
+#[tracing::instrument(name = "Re-encrypting subscribers", skip(pool, cipher))]
+pub async fn reencrypt_subscribers(pool: &PgPool, cipher: &PiiCipher) -> Result<u64, sqlx::Error> {
+    const BATCH_SIZE: i64 = 100;
+    let current_key_id = cipher.current_key_id();
+    let mut updated = 0;
+    let mut skipped = 0;
+    // Paginating on the id, rather than relying on updated rows dropping out of
+    // the selection, lets us move past the skipped ones
+    let mut last_id = Uuid::nil();
+
+    loop {
+        let batch = db::query_as!(
+            StoredSubscriber,
+            r#"
+            SELECT id, email, name, encryption_key_id FROM subscriptions
+            WHERE id > $1 AND encryption_key_id IS DISTINCT FROM $2
+            ORDER BY id
+            LIMIT $3
+            "#,
+            last_id,
+            current_key_id,
+            BATCH_SIZE
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let last = match batch.last() {
+            Some(last) => last.id,
+            None => break,
+        };
+
+        for row in batch {
+            let (email, name) = match row.decrypt(cipher) {
+                Ok(plaintext) => plaintext,
+                Err(e) => {
+                    tracing::error!("Failed to decrypt subscriber {}: {}", row.id, e);
+                    skipped += 1;
+                    continue;
+                }
+            };
+            match update_encrypted_subscriber(pool, row.id, &email, &name, cipher).await {
+                Ok(()) => updated += 1,
+                Err(e) if is_unique_violation(&e) => {
+                    tracing::error!("Subscriber {} has the same email as another one", row.id);
+                    skipped += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        last_id = last;
+    }
+
+    tracing::info!("Re-encrypted {} subscribers, skipped {}", updated, skipped);
+    Ok(updated)
+}
+
+/// Compute the blind index of the subscribers stored in plaintext that don't have
+/// one yet. Returns the number of updated subscribers.
+///
+/// The uniqueness of plaintext emails is enforced on the `email` column, but the
+/// one of encrypted emails only on their blind index. A new encrypted subscriber
+/// could therefore duplicate a plaintext one without a blind index, so this must
+/// complete before accepting encrypted writes: the application only marks itself
+/// as [Readiness](crate::routes::Readiness) afterwards, and
+/// [subscribe](crate::routes::subscribe) rejects sign-ups until then.
+#[tracing::instrument(name = "Backfilling blind indexes", skip(pool, cipher))]
+pub async fn backfill_blind_indexes(pool: &PgPool, cipher: &PiiCipher) -> Result<u64, sqlx::Error> {
+    const BATCH_SIZE: i64 = 1000;
+    let mut updated = 0;
+    let mut last_id = Uuid::nil();
+
+    loop {
+        let batch = db::query_as!(
+            StoredSubscriber,
+            r#"
+            SELECT id, email, name, encryption_key_id FROM subscriptions
+            WHERE id > $1 AND encryption_key_id IS NULL AND email_blind_index IS NULL
+            ORDER BY id
+            LIMIT $2
+            "#,
+            last_id,
+            BATCH_SIZE
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let last = match batch.last() {
+            Some(last) => last.id,
+            None => break,
+        };
+
+        for row in batch {
+            let result = db::query!(
+                "UPDATE subscriptions SET email_blind_index = $1 WHERE id = $2",
+                cipher.blind_index(&row.email),
+                row.id
+            )
+            .execute(pool)
+            .await;
+            match result {
+                Ok(_) => updated += 1,
+                // An encrypted subscriber with the same email already exists
+                Err(e) if is_unique_violation(&e) => {
+                    tracing::error!("Subscriber {} has the same email as another one", row.id);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        last_id = last;
+    }
+
+    tracing::info!("Backfilled {} blind indexes", updated);
+    Ok(updated)
+}
+
+fn is_unique_violation(e: &sqlx::Error) -> bool {
+    match e {
+        // https://www.postgresql.org/docs/current/errcodes-appendix.html
+        sqlx::Error::Database(e) => e.code().as_deref() == Some("23505"),
+        _ => false,
+    }
+}
+
+async fn update_encrypted_subscriber(
+    pool: &PgPool,
+    id: Uuid,
+    email: &str,
+    name: &str,
+    cipher: &PiiCipher,
+) -> Result<(), sqlx::Error> {
+    db::query!(
+        r#"
+        UPDATE subscriptions
+        SET email = $1, name = $2, email_blind_index = $3, encryption_key_id = $4
+        WHERE id = $5
+        "#,
+        cipher.encrypt(email, id, PiiColumn::Email),
+        cipher.encrypt(name, id, PiiColumn::Name),
+        cipher.blind_index(email),
+        cipher.current_key_id(),
+        id
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use uuid::Uuid;
+
+    use super::{PiiCipher, PiiColumn};
+    use crate::configuration::EncryptionConfigurations;
+
+    fn configurations(current_key_id: &str, keys: &[(&str, [u8; 32])]) -> EncryptionConfigurations {
+        EncryptionConfigurations {
+            current_key_id: current_key_id.into(),
+            keys: keys
+                .iter()
+                .map(|(id, key)| (id.to_string(), base64::encode(key)))
+                .collect::<HashMap<_, _>>(),
+            blind_index_key: base64::encode([7u8; 32]),
+        }
+    }
+
+    #[test]
+    fn encrypted_values_can_be_decrypted() {
+        let cipher = PiiCipher::from_configurations(&configurations("a", &[("a", [1; 32])]))
+            .expect("Failed to build cipher");
+
+        let id = Uuid::new_v4();
+
+        let encrypted = cipher.encrypt("nick_bourbaki@gmail.com", id, PiiColumn::Email);
+
+        assert!(encrypted.starts_with("enc:v1:a:"));
+        assert!(!encrypted.contains("bourbaki"));
+        assert_eq!(
+            cipher.decrypt(&encrypted, id, PiiColumn::Email).unwrap(),
+            "nick_bourbaki@gmail.com"
+        );
+    }
+
+    #[test]
+    fn values_encrypted_with_a_previous_key_can_be_decrypted_after_rotation() {
+        let old = PiiCipher::from_configurations(&configurations("a", &[("a", [1; 32])])).unwrap();
+        let rotated =
+            PiiCipher::from_configurations(&configurations("b", &[("a", [1; 32]), ("b", [2; 32])]))
+                .unwrap();
+
+        let id = Uuid::new_v4();
+
+        let encrypted = old.encrypt("Nicolas Bourbaki", id, PiiColumn::Name);
+
+        assert_eq!(
+            rotated.decrypt(&encrypted, id, PiiColumn::Name).unwrap(),
+            "Nicolas Bourbaki"
+        );
+        assert!(rotated
+            .encrypt("Nicolas Bourbaki", id, PiiColumn::Name)
+            .starts_with("enc:v1:b:"));
+    }
+
+    #[test]
+    fn tampered_values_are_rejected() {
+        let cipher =
+            PiiCipher::from_configurations(&configurations("a", &[("a", [1; 32])])).unwrap();
+        let other =
+            PiiCipher::from_configurations(&configurations("a", &[("a", [2; 32])])).unwrap();
+
+        let id = Uuid::new_v4();
+
+        let encrypted = cipher.encrypt("secret", id, PiiColumn::Name);
+        assert!(other.decrypt(&encrypted, id, PiiColumn::Name).is_err());
+        assert!(cipher
+            .decrypt("enc:v1:a:not-base64!", id, PiiColumn::Name)
+            .is_err());
+        assert!(cipher
+            .decrypt("enc:v1:unknown:AAAA", id, PiiColumn::Name)
+            .is_err());
+    }
+
+    #[test]
+    fn values_moved_to_another_column_or_subscriber_are_rejected() {
+        let cipher =
+            PiiCipher::from_configurations(&configurations("a", &[("a", [1; 32])])).unwrap();
+        let id = Uuid::new_v4();
+
+        let encrypted = cipher.encrypt("nick_bourbaki@gmail.com", id, PiiColumn::Email);
+
+        assert!(cipher.decrypt(&encrypted, id, PiiColumn::Name).is_err());
+        assert!(cipher
+            .decrypt(&encrypted, Uuid::new_v4(), PiiColumn::Email)
+            .is_err());
+    }
+
+    #[test]
+    fn values_without_the_encryption_prefix_are_rejected() {
+        let cipher =
+            PiiCipher::from_configurations(&configurations("a", &[("a", [1; 32])])).unwrap();
+
+        assert!(cipher
+            .decrypt("legacy@gmail.com", Uuid::new_v4(), PiiColumn::Email)
+            .is_err());
+    }
+
+    #[test]
+    fn blind_indexes_are_deterministic() {
+        let cipher =
+            PiiCipher::from_configurations(&configurations("a", &[("a", [1; 32])])).unwrap();
+
+        assert_eq!(cipher.blind_index("a@b.com"), cipher.blind_index("a@b.com"));
+        assert_ne!(cipher.blind_index("a@b.com"), cipher.blind_index("c@d.com"));
+    }
+
+    #[test]
+    fn invalid_keys_are_rejected() {
+        let mut short_key = configurations("a", &[("a", [1; 32])]);
+        short_key.keys.insert("a".into(), base64::encode([1u8; 16]));
+        assert!(PiiCipher::from_configurations(&short_key).is_err());
+
+        let missing_current_key = configurations("b", &[("a", [1; 32])]);
+        assert!(PiiCipher::from_configurations(&missing_current_key).is_err());
+
+        let mut empty_blind_index_key = configurations("a", &[("a", [1; 32])]);
+        empty_blind_index_key.blind_index_key = String::new();
+        assert!(PiiCipher::from_configurations(&empty_blind_index_key).is_err());
+
+        let mut short_blind_index_key = configurations("a", &[("a", [1; 32])]);
+        short_blind_index_key.blind_index_key = base64::encode([7u8; 16]);
+        assert!(PiiCipher::from_configurations(&short_blind_index_key).is_err());
+    }
+}
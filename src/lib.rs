@@ -1,4 +1,12 @@
+pub mod advisory_lock;
+pub mod check;
 pub mod configuration;
+pub mod db;
+pub mod domain;
+pub mod encryption;
+pub mod middleware;
 pub mod routes;
+pub mod seed;
 pub mod startup;
 pub mod telemetry;
+pub mod validation;
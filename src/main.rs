@@ -1,11 +1,9 @@
-use std::net::TcpListener;
-
-use sqlx::postgres::PgPoolOptions;
-
 use zero2prod::{
+    check::check,
     configuration::get_configurations,
-    startup::run,
-    telemetry::{get_subscriber, init_subscriber},
+    seed::seed,
+    startup::Application,
+    telemetry::{get_subscriber, init_subscriber, set_pii_redaction},
 };
 
 // #[actix_web::main] is a procedural macro that allow running async code
@@ -18,25 +16,57 @@ use zero2prod::{
 // takes our main asynchronous body and writes the necessary boilerplate to
 // make it run on top of actix’s runtime.
 #[actix_web::main]
-/// The only job of main() is try to call run() depending on its [Result] (Ok or Error).
+/// The only job of main() is try to build the [Application] and run it depending
+/// on its [Result] (Ok or Error).
 async fn main() -> std::io::Result<()> {
     // Setting to log the structured logs generated by the tracing crate's Span.
     let subscriber = get_subscriber("zero2prod".into(), "info".into());
     init_subscriber(subscriber);
 
+    let mut args = std::env::args().skip(1);
+    let command = args.next();
+
+    match command.as_deref() {
+        // `zero2prod check` runs the deploy preflight. It loads the configurations
+        // by itself, to report failures instead of panicking.
+        Some("check") => return check().await,
+        None | Some("seed") => {}
+        // Most likely a typo: starting the server instead would hide it
+        Some(other) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "Unknown command {}. Use `check`, `seed [count]` or no command to start the server.",
+                    other
+                ),
+            ))
+        }
+    }
+
     // Load configurations from file before launching the server
     let configurations = get_configurations().expect("Failed to read configuration file.");
+    set_pii_redaction(&configurations.telemetry)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
 
-    // sqlx::PgPool is built around sqlx::PgConnection to handle multiple concurrent
-    // queries through a connection pool
-    let connection_pool = PgPoolOptions::new().connect_lazy_with(configurations.database.with_db());
-
-    let address = format!(
-        "{}:{}",
-        configurations.application.host, configurations.application.port
-    );
-    let listener = TcpListener::bind(address)?;
+    // `zero2prod seed [count]` populates the database instead of serving requests
+    if command.as_deref() == Some("seed") {
+        let count = match args.next() {
+            Some(count) => count
+                .parse()
+                .ok()
+                .filter(|count| *count > 0)
+                .ok_or_else(|| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("The count must be a positive integer, got {}.", count),
+                    )
+                })?,
+            None => 100,
+        };
+        return seed(&configurations, count).await;
+    }
 
-    run(listener, connection_pool)?.await?;
+    let application = Application::build(configurations).await?;
+    application.run_until_stopped().await?;
     Ok(())
 }
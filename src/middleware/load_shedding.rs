@@ -0,0 +1,249 @@
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    http::{header, StatusCode},
+    Error, HttpResponse, ResponseError,
+};
+
+use crate::routes::ErrorEnvelope;
+
+/// Middleware that rejects requests with `503 SERVICE UNAVAILABLE` when the number of
+/// requests being processed crosses a threshold.
+///
+/// Past a certain point, accepting more work only makes every request slower: they
+/// all end up queueing for a connection from the database pool. Shedding the excess
+/// early keeps latency acceptable for the requests we do accept and gives clients a
+/// clear signal (`Retry-After`) to back off.
+///
+/// Where that point lies depends on how fast the database is at the moment, so the
+/// threshold adapts to the latency of the requests (see [ConcurrencyLimit]).
+///
+/// `actix-web` builds a copy of the [App](actix_web::App) per worker, so [LoadShedding]
+/// must be built _outside_ of `HttpServer::new` and cloned inside it: clones share the
+/// in-flight counter and the threshold is enforced across all the workers.
+#[derive(Clone)]
+pub struct LoadShedding {
+    limit: Arc<ConcurrencyLimit>,
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl LoadShedding {
+    /// Start accepting up to `max_in_flight` requests at a time, going down to
+    /// `min_in_flight` while requests take longer than `target_latency`.
+    pub fn new(min_in_flight: usize, max_in_flight: usize, target_latency: Duration) -> Self {
+        Self {
+            limit: Arc::new(ConcurrencyLimit::new(
+                min_in_flight,
+                max_in_flight,
+                target_latency,
+            )),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+/// Limit of requests in flight, adjusted after every request with AIMD (additive
+/// increase, multiplicative decrease), as TCP does with its congestion window.
+///
+/// Requests slower than the target latency are a sign of saturation: the limit is
+/// cut by a fraction, so it backs off quickly. Requests within the target raise it
+/// by one, so it probes its way back up slowly.
+struct ConcurrencyLimit {
+    current: AtomicUsize,
+    min: usize,
+    max: usize,
+    target_latency: Duration,
+}
+
+impl ConcurrencyLimit {
+    /// Fraction of the limit kept after a slow request.
+    const BACKOFF: f64 = 0.9;
+
+    fn new(min: usize, max: usize, target_latency: Duration) -> Self {
+        Self {
+            current: AtomicUsize::new(max),
+            // With a limit of 0 no request would get through to raise it again
+            min: min.max(1).min(max),
+            max,
+            target_latency,
+        }
+    }
+
+    fn get(&self) -> usize {
+        self.current.load(Ordering::Acquire)
+    }
+
+    fn record(&self, latency: Duration) {
+        let _ = self
+            .current
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |limit| {
+                let limit = if latency > self.target_latency {
+                    (limit as f64 * Self::BACKOFF) as usize
+                } else {
+                    limit + 1
+                };
+                Some(limit.clamp(self.min, self.max))
+            });
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for LoadShedding
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = LoadSheddingMiddleware<S>;
+    type InitError = ();
+    type Future = std::future::Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        std::future::ready(Ok(LoadSheddingMiddleware {
+            service,
+            limiter: self.clone(),
+        }))
+    }
+}
+
+pub struct LoadSheddingMiddleware<S> {
+    service: S,
+    limiter: LoadShedding,
+}
+
+impl<S, B> Service<ServiceRequest> for LoadSheddingMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    actix_web::dev::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        // The guard is created before checking the threshold so the counter is
+        // always decremented, even if the request is shed or the handler is
+        // cancelled halfway through.
+        let guard = InFlightGuard::acquire(&self.limiter.in_flight);
+        let limit = self.limiter.limit.get();
+        if guard.in_flight > limit {
+            tracing::warn!(
+                "Shedding request to {}: {} requests in flight (max {})",
+                req.path(),
+                guard.in_flight - 1,
+                limit
+            );
+            return Box::pin(std::future::ready(Err(Overloaded.into())));
+        }
+
+        let limit = self.limiter.limit.clone();
+        let started = Instant::now();
+        let response = self.service.call(req);
+        Box::pin(async move {
+            let _guard = guard;
+            let response = response.await;
+            limit.record(started.elapsed());
+            response
+        })
+    }
+}
+
+/// Tracks a request for as long as it's in flight.
+struct InFlightGuard {
+    counter: Arc<AtomicUsize>,
+    /// Number of in-flight requests, including this one, when it was acquired.
+    in_flight: usize,
+}
+
+impl InFlightGuard {
+    fn acquire(counter: &Arc<AtomicUsize>) -> Self {
+        let in_flight = counter.fetch_add(1, Ordering::AcqRel) + 1;
+        Self {
+            counter: counter.clone(),
+            in_flight,
+        }
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.counter.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Error returned to the requests shed by [LoadShedding].
+#[derive(Debug)]
+pub struct Overloaded;
+
+impl fmt::Display for Overloaded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The server is overloaded. Please, try again later.")
+    }
+}
+
+impl ResponseError for Overloaded {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        HttpResponse::build(status)
+            .insert_header((header::RETRY_AFTER, "1"))
+            .json(ErrorEnvelope::new(status, self.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::ConcurrencyLimit;
+
+    const TARGET: Duration = Duration::from_millis(100);
+    const SLOW: Duration = Duration::from_millis(500);
+    const FAST: Duration = Duration::from_millis(10);
+
+    #[test]
+    fn slow_requests_shrink_the_limit_down_to_the_minimum() {
+        let limit = ConcurrencyLimit::new(8, 64, TARGET);
+
+        limit.record(SLOW);
+        assert_eq!(limit.get(), 57);
+
+        for _ in 0..100 {
+            limit.record(SLOW);
+        }
+        assert_eq!(limit.get(), 8);
+    }
+
+    #[test]
+    fn fast_requests_grow_the_limit_back_up_to_the_maximum() {
+        let limit = ConcurrencyLimit::new(8, 64, TARGET);
+        for _ in 0..100 {
+            limit.record(SLOW);
+        }
+
+        limit.record(FAST);
+        assert_eq!(limit.get(), 9);
+
+        for _ in 0..100 {
+            limit.record(FAST);
+        }
+        assert_eq!(limit.get(), 64);
+    }
+}
@@ -0,0 +1,7 @@
+mod load_shedding;
+mod timeout;
+mod trusted_proxies;
+
+pub use load_shedding::*;
+pub use timeout::*;
+pub use trusted_proxies::*;
@@ -0,0 +1,160 @@
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    http::StatusCode,
+    rt::time::timeout,
+    Error, HttpMessage, HttpResponse, ResponseError,
+};
+
+use crate::routes::ErrorEnvelope;
+
+/// Middleware that aborts the wrapped handler when it takes longer than the given
+/// [Duration], returning `504 GATEWAY TIMEOUT` to the caller.
+///
+/// It's meant to be registered per resource (e. g., `web::resource(...).wrap(...)`)
+/// so each route gets a budget that matches the work it does.
+///
+/// ### Cancellation
+///
+/// When the deadline is hit, the handler future is dropped. Dropping a future is
+/// how cancellation works in async Rust: every in-flight operation owned by the
+/// handler (e. g., a database query) is dropped with it and never polled again,
+/// so slow dependencies can't pile up work on the workers. Handlers must therefore
+/// be _cancellation safe_: a request can be interrupted at any `.await` point, so
+/// multi-step writes should happen inside a single transaction.
+///
+/// Dropping the future doesn't stop the work that was already handed over to
+/// other systems (e. g., a query running in Postgres), so the middleware also
+/// stores the request [Deadline] in the request extensions, for handlers to
+/// propagate it.
+pub struct Timeout {
+    duration: Duration,
+}
+
+impl Timeout {
+    pub fn new(duration: Duration) -> Self {
+        Self { duration }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Timeout
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = TimeoutMiddleware<S>;
+    type InitError = ();
+    type Future = std::future::Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        std::future::ready(Ok(TimeoutMiddleware {
+            service,
+            duration: self.duration,
+        }))
+    }
+}
+
+pub struct TimeoutMiddleware<S> {
+    service: S,
+    duration: Duration,
+}
+
+impl<S, B> Service<ServiceRequest> for TimeoutMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    actix_web::dev::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let duration = self.duration;
+        let path = req.path().to_owned();
+        req.extensions_mut().insert(Deadline::after(duration));
+        let response = self.service.call(req);
+
+        Box::pin(async move {
+            match timeout(duration, response).await {
+                Ok(response) => response,
+                Err(_) => {
+                    tracing::warn!(
+                        "Request to {} timed out after {} ms",
+                        path,
+                        duration.as_millis()
+                    );
+                    Err(RequestTimedOut(duration).into())
+                }
+            }
+        })
+    }
+}
+
+/// Instant by which the request must be completed, set by the [Timeout] middleware.
+///
+/// Handlers get it through the `web::ReqData<Deadline>` extractor.
+#[derive(Clone, Copy, Debug)]
+pub struct Deadline {
+    at: Instant,
+    budget: Duration,
+}
+
+impl Deadline {
+    pub fn after(budget: Duration) -> Self {
+        Self {
+            at: Instant::now() + budget,
+            budget,
+        }
+    }
+
+    /// Time left before the deadline, zero once it has passed.
+    pub fn remaining(&self) -> Duration {
+        self.at.saturating_duration_since(Instant::now())
+    }
+
+    pub fn is_exceeded(&self) -> bool {
+        Instant::now() >= self.at
+    }
+
+    /// The error the [Timeout] middleware returns once the deadline is exceeded.
+    pub fn exceeded(&self) -> RequestTimedOut {
+        RequestTimedOut(self.budget)
+    }
+}
+
+/// Error returned when a handler doesn't complete within its [Timeout].
+#[derive(Debug)]
+pub struct RequestTimedOut(Duration);
+
+impl fmt::Display for RequestTimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The request did not complete within {} ms.",
+            self.0.as_millis()
+        )
+    }
+}
+
+impl ResponseError for RequestTimedOut {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::GATEWAY_TIMEOUT
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        HttpResponse::build(status).json(ErrorEnvelope::new(status, self.to_string()))
+    }
+}
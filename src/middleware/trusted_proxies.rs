@@ -0,0 +1,263 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{self, HeaderName, HeaderValue},
+    Error,
+};
+
+/// Headers set by reverse proxies to describe the original request.
+const FORWARDED_HEADERS: [&str; 4] = [
+    "x-forwarded-for",
+    "x-forwarded-proto",
+    "x-forwarded-host",
+    "x-real-ip",
+];
+
+/// Middleware that drops the `Forwarded` and `X-Forwarded-*` headers unless the
+/// request comes from one of the trusted reverse proxies.
+///
+/// `actix-web`'s [ConnectionInfo](actix_web::dev::ConnectionInfo) derives the client
+/// IP, scheme and host from those headers whenever they are present, so any client
+/// could spoof them. Stripping them at the edge of the [App](actix_web::App) means
+/// that everything downstream (e. g., the client IP in the request logs) can rely on
+/// `connection_info()`. It must therefore be the outermost middleware.
+///
+/// Even behind a trusted proxy, only part of the headers is trustworthy: proxies
+/// append the address of their peer to the `for` list of `Forwarded` and to
+/// `X-Forwarded-For`, after whatever the client sent. `ConnectionInfo` picks the
+/// first entry, so we rewrite both headers down to the entry of the closest hop
+/// that isn't one of our proxies. `X-Forwarded-Proto` and `X-Forwarded-Host` are
+/// kept as they are: proxies are expected to overwrite them.
+#[derive(Clone)]
+pub struct TrustedProxies {
+    proxies: Arc<Vec<IpAddr>>,
+}
+
+impl TrustedProxies {
+    pub fn new(proxies: Vec<IpAddr>) -> Self {
+        Self {
+            proxies: Arc::new(proxies),
+        }
+    }
+
+    fn trusts(&self, request: &ServiceRequest) -> bool {
+        request
+            .peer_addr()
+            .map(|peer| self.proxies.contains(&peer.ip()))
+            .unwrap_or(false)
+    }
+
+    /// Index of the client in a list of hops, ordered from the client to the
+    /// closest proxy: the last hop that isn't one of our proxies, or the first hop
+    /// if they all are.
+    fn client_hop(&self, hops: &[&str]) -> Option<usize> {
+        let trusted = |hop: &str| {
+            parse_ip(hop)
+                .map(|ip| self.proxies.contains(&ip))
+                .unwrap_or(false)
+        };
+        match hops.iter().rposition(|hop| !trusted(hop)) {
+            Some(client) => Some(client),
+            None if hops.is_empty() => None,
+            None => Some(0),
+        }
+    }
+
+    /// Keep only the element of `Forwarded` and the entry of `X-Forwarded-For`
+    /// that describe the client, see [TrustedProxies].
+    fn resolve_client(&self, request: &mut ServiceRequest) {
+        let elements = list_values(request, &header::FORWARDED);
+        let hops: Vec<&str> = elements
+            .iter()
+            .map(|element| forwarded_for(element).unwrap_or_default())
+            .collect();
+        let client = self.client_hop(&hops).map(|i| elements[i].as_str());
+        replace_header(request, header::FORWARDED, client);
+
+        let name = HeaderName::from_static("x-forwarded-for");
+        let entries = list_values(request, &name);
+        let hops: Vec<&str> = entries.iter().map(String::as_str).collect();
+        let client = self.client_hop(&hops).map(|i| hops[i]);
+        replace_header(request, name, client);
+    }
+}
+
+/// Comma-separated values of every `name` header, in order.
+fn list_values(request: &ServiceRequest, name: &HeaderName) -> Vec<String> {
+    request
+        .headers()
+        .get_all(name)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|value| value.trim().to_owned())
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+/// Value of the `for` parameter of a `Forwarded` element
+/// (e. g., `for=192.0.2.60;proto=http`).
+fn forwarded_for(element: &str) -> Option<&str> {
+    element.split(';').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        if key.trim().eq_ignore_ascii_case("for") {
+            Some(value.trim().trim_matches('"'))
+        } else {
+            None
+        }
+    })
+}
+
+/// Parse an IP out of a hop, which may come with a port (`192.0.2.60:4711`) and,
+/// for IPv6, square brackets (`[2001:db8::1]:4711`).
+fn parse_ip(hop: &str) -> Option<IpAddr> {
+    hop.parse::<IpAddr>()
+        .or_else(|_| hop.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .or_else(|_| hop.trim_start_matches('[').trim_end_matches(']').parse())
+        .ok()
+}
+
+fn replace_header(request: &mut ServiceRequest, name: HeaderName, value: Option<&str>) {
+    let headers = request.headers_mut();
+    match value.and_then(|value| HeaderValue::from_str(value).ok()) {
+        Some(value) => {
+            headers.insert(name, value);
+        }
+        None => {
+            headers.remove(name);
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for TrustedProxies
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = TrustedProxiesMiddleware<S>;
+    type InitError = ();
+    type Future = std::future::Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        std::future::ready(Ok(TrustedProxiesMiddleware {
+            service,
+            proxies: self.clone(),
+        }))
+    }
+}
+
+pub struct TrustedProxiesMiddleware<S> {
+    service: S,
+    proxies: TrustedProxies,
+}
+
+impl<S, B> Service<ServiceRequest> for TrustedProxiesMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = S::Future;
+
+    actix_web::dev::forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        if self.proxies.trusts(&req) {
+            self.proxies.resolve_client(&mut req);
+        } else {
+            let headers = req.headers_mut();
+            headers.remove(header::FORWARDED);
+            for &name in FORWARDED_HEADERS.iter() {
+                headers.remove(HeaderName::from_static(name));
+            }
+        }
+
+        self.service.call(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test, web, App, HttpRequest};
+
+    use super::TrustedProxies;
+
+    async fn client_info(req: HttpRequest) -> String {
+        let info = req.connection_info();
+        format!(
+            "{} {}",
+            info.scheme(),
+            info.realip_remote_addr().unwrap_or_default()
+        )
+    }
+
+    async fn client_info_from(peer: &str, headers: &[(&str, &str)]) -> String {
+        let app = test::init_service(
+            App::new()
+                .wrap(TrustedProxies::new(vec![
+                    "10.0.0.1".parse().unwrap(),
+                    "10.0.0.2".parse().unwrap(),
+                ]))
+                .route("/", web::get().to(client_info)),
+        )
+        .await;
+        let mut request = test::TestRequest::get()
+            .uri("/")
+            .peer_addr(peer.parse().unwrap());
+        for &header in headers {
+            request = request.insert_header(header);
+        }
+        let body = test::read_response(&app, request.to_request()).await;
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn forwarded_headers_from_trusted_proxies_are_honoured() {
+        let headers = [
+            ("X-Forwarded-For", "203.0.113.7"),
+            ("X-Forwarded-Proto", "https"),
+        ];
+        assert_eq!(
+            client_info_from("10.0.0.1:4000", &headers).await,
+            "https 203.0.113.7"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn forwarded_headers_from_untrusted_peers_are_dropped() {
+        let headers = [
+            ("X-Forwarded-For", "203.0.113.7"),
+            ("X-Forwarded-Proto", "https"),
+        ];
+        let info = client_info_from("192.0.2.1:4000", &headers).await;
+        assert!(info.starts_with("http 192.0.2.1"));
+    }
+
+    #[actix_rt::test]
+    async fn hops_sent_by_the_client_through_trusted_proxies_are_ignored() {
+        let headers = [("X-Forwarded-For", "spoofed, 203.0.113.7")];
+        assert_eq!(
+            client_info_from("10.0.0.1:4000", &headers).await,
+            "http 203.0.113.7"
+        );
+
+        let headers = [("X-Forwarded-For", "198.51.100.1, 203.0.113.7, 10.0.0.2")];
+        assert_eq!(
+            client_info_from("10.0.0.1:4000", &headers).await,
+            "http 203.0.113.7"
+        );
+
+        let headers = [(
+            "Forwarded",
+            "for=spoofed;proto=https, for=203.0.113.7;proto=http, for=\"10.0.0.2:4711\"",
+        )];
+        assert_eq!(
+            client_info_from("10.0.0.1:4000", &headers).await,
+            "http 203.0.113.7"
+        );
+    }
+}
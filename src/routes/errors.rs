@@ -0,0 +1,156 @@
+use actix_web::{
+    http::{header, Method, StatusCode},
+    web, HttpRequest, HttpResponse, Route,
+};
+
+use crate::validation::FieldError;
+
+/// JSON envelope returned to API clients when a request fails.
+///
+/// ```json
+/// { "error": { "status": 404, "message": "..." } }
+/// ```
+///
+/// Validation failures also carry the list of offending `fields`.
+#[derive(serde::Serialize)]
+pub struct ErrorEnvelope {
+    error: ErrorBody,
+}
+
+#[derive(serde::Serialize)]
+struct ErrorBody {
+    status: u16,
+    message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    fields: Vec<FieldError>,
+}
+
+impl ErrorEnvelope {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            error: ErrorBody {
+                status: status.as_u16(),
+                message: message.into(),
+                fields: Vec::new(),
+            },
+        }
+    }
+
+    pub fn with_fields(mut self, fields: &[FieldError]) -> Self {
+        self.error.fields = fields.to_vec();
+        self
+    }
+}
+
+/// Default handler for requests that don't match any route.
+pub async fn not_found(request: HttpRequest) -> HttpResponse {
+    error_response(
+        &request,
+        StatusCode::NOT_FOUND,
+        "The resource you are looking for does not exist.",
+    )
+}
+
+/// Default route for requests that match a resource but not any of its methods.
+///
+/// RFC 7231 requires `405 METHOD NOT ALLOWED` responses to list the methods the
+/// resource does support in the `Allow` header, so each resource registers its own.
+pub fn method_not_allowed(allowed: &'static [Method]) -> Route {
+    web::route().to(move |request: HttpRequest| async move {
+        let mut response = error_response(
+            &request,
+            StatusCode::METHOD_NOT_ALLOWED,
+            "The method is not allowed for the requested resource.",
+        );
+        let allowed = allowed
+            .iter()
+            .map(Method::as_str)
+            .collect::<Vec<_>>()
+            .join(", ");
+        response.headers_mut().insert(
+            header::ALLOW,
+            header::HeaderValue::from_str(&allowed).expect("Methods are valid header values"),
+        );
+        response
+    })
+}
+
+/// Build an error response in the representation preferred by the caller.
+///
+/// Browsers send `Accept: text/html` on navigation, so they get a branded HTML page.
+/// Everything else (API clients, `curl`, etc.) gets the [ErrorEnvelope] as JSON.
+pub fn error_response(request: &HttpRequest, status: StatusCode, message: &str) -> HttpResponse {
+    error_response_with_fields(request, status, message, &[])
+}
+
+/// Same as [error_response], listing the offending fields of a validation failure.
+pub fn error_response_with_fields(
+    request: &HttpRequest,
+    status: StatusCode,
+    message: &str,
+    fields: &[FieldError],
+) -> HttpResponse {
+    if accepts_html(request) {
+        HttpResponse::build(status)
+            .content_type("text/html; charset=utf-8")
+            .body(error_page(status, message, fields))
+    } else {
+        HttpResponse::build(status).json(ErrorEnvelope::new(status, message).with_fields(fields))
+    }
+}
+
+fn accepts_html(request: &HttpRequest) -> bool {
+    request
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .map(|accept| accept.contains("text/html"))
+        .unwrap_or(false)
+}
+
+fn error_page(status: StatusCode, message: &str, fields: &[FieldError]) -> String {
+    let reason = status.canonical_reason().unwrap_or("Error");
+    // Field errors can echo the submitted values back
+    let fields: String = fields
+        .iter()
+        .map(|e| {
+            format!(
+                "\n        <li><strong>{}</strong>: {}</li>",
+                escape_html(&e.field),
+                escape_html(&e.message)
+            )
+        })
+        .collect();
+    let fields = if fields.is_empty() {
+        fields
+    } else {
+        format!("\n    <ul>{}\n    </ul>", fields)
+    };
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="utf-8">
+    <title>{code} {reason} | zero2prod</title>
+</head>
+<body>
+    <h1>{code} {reason}</h1>
+    <p>{message}</p>{fields}
+    <p><a href="/">zero2prod newsletter</a></p>
+</body>
+</html>
+"#,
+        code = status.as_u16(),
+        reason = reason,
+        message = message,
+        fields = fields,
+    )
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
@@ -1,4 +1,14 @@
-use actix_web::HttpResponse;
+use std::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use actix_web::{
+    http::{header, StatusCode},
+    web, HttpResponse, ResponseError,
+};
+
+use crate::routes::ErrorEnvelope;
 
 /// Endpoint to  verify the application es up and ready.
 ///
@@ -7,6 +17,68 @@ use actix_web::HttpResponse;
 /// It can be used to customize some alert system to get noitified when
 /// the API is down. Or trigger a restart in the context of container
 /// orchestration when the API has become unresponsive.
+///
+/// This is the _liveness_ probe: it must stay cheap and never touch any
+/// dependency, otherwise a slow database would get healthy instances restarted.
 pub async fn health_check() -> HttpResponse {
     HttpResponse::Ok().finish()
 }
+
+/// Shared flag telling whether the application finished warming up its dependencies.
+///
+/// It starts as _not ready_ and it's flipped once the warm-up kicked off by
+/// `Application::build` completes. Endpoints that depend on the warm-up (e. g.,
+/// [subscribe](crate::routes::subscribe)) check it too and fail with [NotReady]
+/// until then, so they're safe even when the readiness probe is bypassed.
+#[derive(Default)]
+pub struct Readiness(AtomicBool);
+
+impl Readiness {
+    pub fn mark_ready(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+/// Error returned by the endpoints that can't serve requests before the application
+/// is ready.
+#[derive(Debug)]
+pub struct NotReady;
+
+impl fmt::Display for NotReady {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The server is starting. Please, try again later.")
+    }
+}
+
+impl ResponseError for NotReady {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        HttpResponse::build(status)
+            .insert_header((header::RETRY_AFTER, "1"))
+            .json(ErrorEnvelope::new(status, self.to_string()))
+    }
+}
+
+/// Endpoint to verify the application is ready to receive traffic.
+///
+/// Responses:
+/// - 200 OK: dependencies are warmed up
+/// - 503 SERVICE UNAVAILABLE: the instance is still starting
+///
+/// Orchestrators should use this _readiness_ probe to decide whether to route
+/// traffic to the instance, and [health_check] to decide whether to restart it.
+pub async fn readiness_check(readiness: web::Data<Readiness>) -> HttpResponse {
+    if readiness.is_ready() {
+        HttpResponse::Ok().finish()
+    } else {
+        HttpResponse::ServiceUnavailable().finish()
+    }
+}
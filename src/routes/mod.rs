@@ -1,5 +1,7 @@
+mod errors;
 mod health_check;
 mod subscriptions;
 
+pub use errors::*;
 pub use health_check::*;
 pub use subscriptions::*;
@@ -1,29 +1,161 @@
-use actix_web::{web, HttpResponse};
+use std::collections::HashMap;
+
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
 use chrono::Utc;
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
+use crate::{
+    configuration::SubscriptionsConfigurations,
+    db,
+    domain::{
+        Attribution, CustomFields, NewSubscriber, SubscriberEmail, SubscriberName,
+        SubscriptionSource,
+    },
+    encryption::{PiiCipher, PiiColumn},
+    middleware::Deadline,
+    routes::{NotReady, Readiness},
+    telemetry::{redact, redact_database_error},
+    validation::{Validate, ValidatedForm, ValidationErrors},
+};
+
+/// Fields of [FormData] picked up before the custom fields: a custom field can't
+/// have any of these names.
+pub const RESERVED_FIELDS: [&str; 9] = [
+    "email",
+    "name",
+    "source",
+    "ref",
+    "utm_source",
+    "utm_medium",
+    "utm_campaign",
+    "utm_term",
+    "utm_content",
+];
+
 /// Struct to model the inputed form data when sending a `POST` request through
 /// [subscribe] endpoint.
 #[derive(serde::Deserialize)]
 pub struct FormData {
-    email: String,
-    name: String,
+    // Optional, so that a missing field is reported along with the invalid ones
+    email: Option<String>,
+    name: Option<String>,
+    /// Channel of the subscription, one of [SubscriptionSource].
+    source: Option<String>,
+    // Flattened fields are filled in order: attribution parameters must be
+    // picked up before the catch-all custom fields
+    #[serde(flatten)]
+    attribution: AttributionData,
+    /// Any other field, candidate to be a custom field.
+    #[serde(flatten)]
+    custom_fields: HashMap<String, String>,
+}
+
+/// Referral code and UTM parameters of a sign-up.
+///
+/// They can be sent either as form fields (e. g., hidden inputs in the sign-up form)
+/// or in the query string of the `POST` request. Form fields take precedence.
+#[derive(serde::Deserialize, Default)]
+pub struct AttributionData {
+    #[serde(rename = "ref")]
+    referrer: Option<String>,
+    utm_source: Option<String>,
+    utm_medium: Option<String>,
+    utm_campaign: Option<String>,
+    utm_term: Option<String>,
+    utm_content: Option<String>,
+}
+
+impl AttributionData {
+    fn into_attribution(self, fallback: AttributionData) -> Attribution {
+        Attribution {
+            referrer: Attribution::parse_value(self.referrer.or(fallback.referrer)),
+            utm_source: Attribution::parse_value(self.utm_source.or(fallback.utm_source)),
+            utm_medium: Attribution::parse_value(self.utm_medium.or(fallback.utm_medium)),
+            utm_campaign: Attribution::parse_value(self.utm_campaign.or(fallback.utm_campaign)),
+            utm_term: Attribution::parse_value(self.utm_term.or(fallback.utm_term)),
+            utm_content: Attribution::parse_value(self.utm_content.or(fallback.utm_content)),
+        }
+    }
+}
+
+impl Validate for NewSubscriber {
+    type Input = FormData;
+
+    fn validate(form: FormData, req: &HttpRequest) -> Result<Self, ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        let email = errors.require("email", form.email).and_then(|email| {
+            SubscriberEmail::parse(email)
+                .map_err(|e| errors.add("email", e))
+                .ok()
+        });
+        let name = errors.require("name", form.name).and_then(|name| {
+            SubscriberName::parse(name)
+                .map_err(|e| errors.add("name", e))
+                .ok()
+        });
+        // Blank values are the same as a missing one, as with attribution
+        let source = match form.source.filter(|source| !source.trim().is_empty()) {
+            Some(source) => SubscriptionSource::parse(&source)
+                .map(Some)
+                .map_err(|e| errors.add("source", e))
+                .ok(),
+            None => Some(None),
+        };
+        let definitions = req
+            .app_data::<web::Data<SubscriptionsConfigurations>>()
+            .map(|c| c.custom_fields.as_slice())
+            .unwrap_or_default();
+        let custom_fields = CustomFields::parse(form.custom_fields, definitions)
+            .map_err(|field_errors| {
+                for (field, e) in field_errors {
+                    errors.add(field, e);
+                }
+            })
+            .ok();
+        // Attribution never fails the subscription: a malformed query string is
+        // just ignored
+        let query = web::Query::<AttributionData>::from_query(req.query_string())
+            .map(|query| query.into_inner())
+            .unwrap_or_default();
+        let attribution = form.attribution.into_attribution(query);
+
+        match (email, name, custom_fields, source) {
+            (Some(email), Some(name), Some(custom_fields), Some(source)) => Ok(Self {
+                email,
+                name,
+                custom_fields,
+                attribution,
+                source,
+            }),
+            _ => Err(errors),
+        }
+    }
 }
 
 /// Endpoint to add new user to newsletter.
 ///
 /// Responses:
 /// - 200 OK: successful subscription
-/// - 400 BAD REQUEST: name or email field is missing
+/// - 400 BAD REQUEST: a field (e. g., name or email) is missing or invalid
+/// - 500 INTERNAL SERVER ERROR: the subscriber couldn't be saved (e. g., the email
+///   is already subscribed)
+/// - 503 SERVICE UNAVAILABLE: too many requests in flight, see
+///   [LoadShedding](crate::middleware::LoadShedding), or the instance is still
+///   getting ready, see [Readiness]
+/// - 504 GATEWAY TIMEOUT: the request didn't complete in time, see
+///   [Timeout](crate::middleware::Timeout)
+///
+/// It uses our [ValidatedForm] extractor, built on top of actix-web's [web::Form]
+/// extractor. The extractors are in charge of handling failure responses. `actix-web`
+/// invokes [web::FromRequest]'s `from_request()` (`FromRequest` is implemented by
+/// `Form` and any other extractor) for all `subscribe`'s input arguments: in this case
+/// `Form::from_request`. `Form::from_request` tries to deserialise the body into
+/// [FormData] (through `serde_urlencoded` and the `Deserialize` implementation of
+/// [FormData]), automatically generated by `#[derive(serde::Deserialize)]`.
 ///
-/// It uses actix-web's [web::Form] extractor. The extractors are in charge of handling
-/// failure responses. `actix-web` invokes [web::FromRequest]'s `from_request()`
-/// (`FromRequest` is implemented by `Form` and any other extractor) for all `subscribe`'s
-/// input arguments: in this case `Form::from_request`. `Form::from_request` tries to
-/// deserialise the body into [FormData] (through `serde_urlencoded` and the `Deserialize`
-/// implementation of [FormData]), automatically generated by
-/// `#[derive(serde::Deserialize)]`.
+/// [ValidatedForm] then parses [FormData] into a [NewSubscriber], returning a `400 BAD
+/// REQUEST` with per-field details if any of the fields is invalid.
 ///
 /// If the extraction succeed, the hanlder is called normally, but
 /// if it fails, then the corresponding error (`400 BAD REQUEST`) is returned to the
@@ -32,7 +164,9 @@ pub struct FormData {
 ///
 /// Similarly, the [web::Data] extractor allows us to get the connection pool from
 /// application state as defined in `run`. In other contexts, this could be referred
-/// as _dependency injection_.
+/// as _dependency injection_. The [Deadline] is set by the
+/// [Timeout](crate::middleware::Timeout) middleware: without it, database work is
+/// unbounded.
 ///
 /// ### Instrumentation
 ///
@@ -40,18 +174,22 @@ pub struct FormData {
 /// of the function invocation. It automatically attaches all arguments passed to the function
 /// to the context of the span --in this case, `form` and `pool`. We use the `skipe` directive
 /// to no explicitely tell `tracing` to ignore them in logs. Also, the `fields` directive enriches
-/// the span's context --leverages the same syntax as `info_span!` macro.
+/// the span's context --leverages the same syntax as `info_span!` macro. Email and name
+/// go through [redact], so they only show up in logs when redaction is turned off.
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, pool),
+    skip(form, pool, cipher, readiness, deadline),
     fields(
-        email = %form.email,
-        name = %form.name
+        email = %redact(form.0.email.as_ref()),
+        name = %redact(form.0.name.as_ref())
     )
 )]
 pub async fn subscribe(
-    form: web::Form<FormData>,
+    form: ValidatedForm<NewSubscriber>,
     pool: web::Data<PgPool>,
+    cipher: web::Data<Option<PiiCipher>>,
+    readiness: web::Data<Readiness>,
+    deadline: Option<web::ReqData<Deadline>>,
 ) -> Result<HttpResponse, HttpResponse> {
     // We're using the tracing crate to print in terminal the logs captured
     // by actix_web::middlewares::Logger.
@@ -61,38 +199,119 @@ pub async fn subscribe(
     // for the process being logged. The span is "exit" when _request_span_guard
     // is dropped at the end of subscribe
 
-    insert_subscriber(&pool, &form)
+    // Until the blind indexes are backfilled, an encrypted sign-up could duplicate
+    // a plaintext subscriber, see backfill_blind_indexes
+    if !readiness.is_ready() {
+        return Err(NotReady.error_response());
+    }
+
+    // The route timeout bounds the database work too, see Timeout
+    let deadline = deadline.map(|deadline| deadline.into_inner());
+    let transaction = match deadline {
+        Some(deadline) => db::begin_with_deadline(&pool, deadline).await,
+        None => pool.begin().await,
+    };
+    let mut transaction = transaction.map_err(|_| database_error(deadline.as_ref()))?;
+    insert_subscriber(
+        &mut transaction,
+        &form.into_inner(),
+        cipher.get_ref().as_ref(),
+    )
+    .await
+    .map_err(|_| database_error(deadline.as_ref()))?;
+    transaction
+        .commit()
         .await
-        .map_err(|_| HttpResponse::InternalServerError().finish())?;
+        .map_err(|_| database_error(deadline.as_ref()))?;
 
     Ok(HttpResponse::Ok().finish())
 }
 
+/// Database failures past the deadline are reported as timeouts, the same way the
+/// [Timeout](crate::middleware::Timeout) middleware would.
+fn database_error(deadline: Option<&Deadline>) -> HttpResponse {
+    match deadline {
+        Some(deadline) if deadline.is_exceeded() => deadline.exceeded().error_response(),
+        _ => HttpResponse::InternalServerError().finish(),
+    }
+}
+
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
-    skip(form, pool)
+    skip(new_subscriber, transaction, cipher)
 )]
-pub async fn insert_subscriber(pool: &PgPool, form: &FormData) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+pub async fn insert_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    new_subscriber: &NewSubscriber,
+    cipher: Option<&PiiCipher>,
+) -> Result<(), sqlx::Error> {
+    let id = Uuid::new_v4();
+    let email = new_subscriber.email.as_ref();
+    let name = new_subscriber.name.as_ref();
+    // Personally identifiable data is encrypted before leaving the application
+    // when encryption at rest is enabled
+    let (email, name, email_blind_index, encryption_key_id) = match cipher {
+        Some(cipher) => (
+            cipher.encrypt(email, id, PiiColumn::Email),
+            cipher.encrypt(name, id, PiiColumn::Name),
+            Some(cipher.blind_index(email)),
+            Some(cipher.current_key_id()),
+        ),
+        None => (email.to_owned(), name.to_owned(), None, None),
+    };
+
+    db::query!(
         r#"
-        INSERT INTO subscriptions (id, email, name, subscribed_at)
-        VALUES ($1, $2, $3, $4)
+        INSERT INTO subscriptions (
+            id, email, name, subscribed_at, custom_fields,
+            referrer, utm_source, utm_medium, utm_campaign, utm_term, utm_content,
+            email_blind_index, encryption_key_id, source
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
         "#,
-        Uuid::new_v4(),
-        form.email,
-        form.name,
-        Utc::now()
+        id,
+        email,
+        name,
+        Utc::now(),
+        new_subscriber.custom_fields.to_json(),
+        new_subscriber.attribution.referrer,
+        new_subscriber.attribution.utm_source,
+        new_subscriber.attribution.utm_medium,
+        new_subscriber.attribution.utm_campaign,
+        new_subscriber.attribution.utm_term,
+        new_subscriber.attribution.utm_content,
+        email_blind_index,
+        encryption_key_id,
+        new_subscriber.source.map(|source| source.as_str())
     )
     // sqlx doesn't allow to run multiple queries concurrently over the same DB connection.
     // That's why it requires a mutable reference (that is, a "unique" refence) to the
-    // connection. We can't get a mutable reference from web::Data, so we need to use
-    // something else, like PgPool in this case.
-    .execute(pool)
+    // connection. A transaction holds a single connection, so the caller lends it to us
+    // mutably.
+    .execute(transaction)
     .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
+    .inspect_err(|e| tracing::error!("Failed to execute query: {}", redact_database_error(e)))?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use actix_web::web;
+
+    use super::{FormData, RESERVED_FIELDS};
+
+    #[test]
+    fn reserved_fields_never_reach_the_custom_fields() {
+        let body = RESERVED_FIELDS
+            .iter()
+            .map(|field| format!("{}=value", field))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        // Same encoding as form bodies
+        let form = web::Query::<FormData>::from_query(&body).unwrap();
+
+        assert!(form.custom_fields.is_empty());
+    }
+}
@@ -0,0 +1,112 @@
+use std::io::Error;
+
+use fake::{
+    faker::name::en::{FirstName, LastName},
+    Fake,
+};
+use rand::{seq::SliceRandom, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use sqlx::PgPool;
+
+use crate::{
+    configuration::Configurations,
+    domain::{
+        Attribution, CustomFields, NewSubscriber, SubscriberEmail, SubscriberName,
+        SubscriptionSource,
+    },
+    encryption::PiiCipher,
+    routes::insert_subscriber,
+    startup::get_connection_pool,
+};
+
+/// Seed of the random number generator: the same seed always produces the same
+/// subscribers, so demos and performance tests are reproducible.
+///
+/// That only holds with a generator whose output is stable across versions and
+/// platforms: `rand`'s `StdRng` doesn't guarantee it, ChaCha does. Names come from
+/// `fake`'s word lists, which can change between its releases, so the data is the
+/// same as long as `Cargo.lock` is.
+pub const SEED: u64 = 42;
+
+/// Domains reserved for documentation (RFC 2606 and RFC 6761), so seeded emails
+/// can never reach real mailboxes. `fake`'s providers are real ones.
+const DOMAINS: [&str; 4] = ["example.com", "example.org", "example.net", "mail.example"];
+const UTM_SOURCES: [&str; 4] = ["twitter", "newsletter", "blog", "podcast"];
+
+/// Populate the database configured in `configurations` with `count` subscribers.
+///
+/// Entry point of `zero2prod seed [count]`. It's meant to run against a fresh
+/// database: emails are unique, so seeding twice the same database fails.
+pub async fn seed(configurations: &Configurations, count: usize) -> Result<(), Error> {
+    let pool = get_connection_pool(&configurations.database);
+    let cipher = configurations
+        .encryption
+        .as_ref()
+        .map(PiiCipher::from_configurations)
+        .transpose()
+        .map_err(|e| Error::new(std::io::ErrorKind::InvalidInput, e))?;
+
+    seed_subscribers(&pool, count, SEED, cipher.as_ref())
+        .await
+        .map_err(Error::other)?;
+
+    Ok(())
+}
+
+/// Insert `count` realistic subscribers, generated from `seed`.
+#[tracing::instrument(name = "Seeding subscribers", skip(pool, cipher))]
+pub async fn seed_subscribers(
+    pool: &PgPool,
+    count: usize,
+    seed: u64,
+    cipher: Option<&PiiCipher>,
+) -> Result<(), sqlx::Error> {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let mut transaction = pool.begin().await?;
+    for i in 0..count {
+        insert_subscriber(&mut transaction, &fake_subscriber(&mut rng, i), cipher).await?;
+    }
+    transaction.commit().await?;
+
+    tracing::info!("Seeded {} subscribers", count);
+    Ok(())
+}
+
+/// Build the `i`-th fake subscriber. The index keeps emails unique.
+fn fake_subscriber(rng: &mut ChaCha8Rng, i: usize) -> NewSubscriber {
+    let first_name: String = FirstName().fake_with_rng(rng);
+    let last_name: String = LastName().fake_with_rng(rng);
+    let domain = DOMAINS.choose(rng).unwrap();
+    // Some last names have apostrophes or spaces (e. g., O'Hara)
+    let local_part = |name: &str| -> String {
+        name.chars()
+            .filter(char::is_ascii_alphanumeric)
+            .collect::<String>()
+            .to_lowercase()
+    };
+    let email = format!(
+        "{}.{}.{}@{}",
+        local_part(&first_name),
+        local_part(&last_name),
+        i,
+        domain
+    );
+    // Roughly a third of the subscribers comes from a campaign
+    let utm_source = if rng.gen_ratio(1, 3) {
+        UTM_SOURCES.choose(rng).map(|source| source.to_string())
+    } else {
+        None
+    };
+
+    NewSubscriber {
+        email: SubscriberEmail::parse(email).expect("Seeded emails are valid"),
+        name: SubscriberName::parse(format!("{} {}", first_name, last_name))
+            .expect("Seeded names are valid"),
+        custom_fields: CustomFields::default(),
+        attribution: Attribution {
+            utm_source,
+            ..Attribution::default()
+        },
+        source: SubscriptionSource::ALL.choose(rng).copied(),
+    }
+}
@@ -1,10 +1,196 @@
-use std::{io::Error, net::TcpListener};
+use std::{
+    io::Error,
+    net::{SocketAddr, TcpListener, ToSocketAddrs},
+    time::Duration,
+};
 
-use actix_web::{dev::Server, web, App, HttpServer};
-use sqlx::PgPool;
+use actix_web::{dev::Server, http::Method, web, App, HttpServer};
+use socket2::{Domain, Protocol, Socket, Type};
+use sqlx::{postgres::PgPoolOptions, PgPool};
 use tracing_actix_web::TracingLogger;
 
-use crate::routes::{health_check, subscribe};
+use crate::{
+    advisory_lock::{keys, run_exclusively},
+    configuration::{
+        ApplicationConfigurations, Configurations, DatabaseConfigurations,
+        SubscriptionsConfigurations,
+    },
+    encryption::{backfill_blind_indexes, reencrypt_subscribers, PiiCipher},
+    middleware::{LoadShedding, Timeout, TrustedProxies},
+    routes::{health_check, method_not_allowed, not_found, readiness_check, subscribe, Readiness},
+    telemetry::redact_database_error,
+};
+
+/// Wrapper around the running [Server] and the information we need to retrieve
+/// from it after it has been built.
+///
+/// `Application::build` takes care of the whole startup sequence: it binds the
+/// listener, builds the connection pool and kicks off the warm-up of the
+/// dependencies, which runs in the background until the instance can be reported as
+/// _ready_ (see [get_ready]). When the configured port is 0 the OS picks a random
+/// one, so we keep the actual port around to be able to reach the server (e. g., in
+/// integration tests).
+pub struct Application {
+    port: u16,
+    server: Server,
+}
+
+impl Application {
+    pub async fn build(configurations: Configurations) -> Result<Self, Error> {
+        let connection_pool = get_connection_pool(&configurations.database);
+        let cipher = configurations
+            .encryption
+            .as_ref()
+            .map(PiiCipher::from_configurations)
+            .transpose()
+            .map_err(|e| Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        let cipher = web::Data::new(cipher);
+
+        let address = format!(
+            "{}:{}",
+            configurations.application.host, configurations.application.port
+        );
+        let listener = bind_listener(&address, configurations.application.server.backlog)?;
+        let port = listener.local_addr().unwrap().port();
+
+        // The server starts accepting connections right away, so liveness probes
+        // get an answer while we're still warming up. The readiness probe and the
+        // endpoints depending on the warm-up, on the other hand, keep failing until
+        // get_ready() completes.
+        let readiness = web::Data::new(Readiness::default());
+        let server = run(
+            listener,
+            connection_pool.clone(),
+            readiness.clone(),
+            &configurations.application,
+            configurations.subscriptions,
+            cipher.clone(),
+        )?;
+
+        actix_web::rt::spawn(get_ready(connection_pool, readiness, cipher));
+
+        Ok(Self { port, server })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Consume the [Application] and run it until it gets stopped.
+    ///
+    /// It's expressive to take ownership of `self` here: once the server is running
+    /// there's nothing else we can do with the [Application].
+    pub async fn run_until_stopped(self) -> Result<(), Error> {
+        self.server.await
+    }
+}
+
+/// Bind a [TcpListener] with a custom backlog.
+///
+/// `HttpServer::backlog` only applies to the sockets `actix-web` binds by itself, but
+/// we hand over an already bound listener to it. [TcpListener::bind] doesn't let us
+/// choose the size of the queue of pending connections, so we set up the socket with
+/// `socket2`, mirroring what `actix-web` does internally.
+fn bind_listener(address: &str, backlog: u32) -> Result<TcpListener, Error> {
+    let address: SocketAddr = address.to_socket_addrs()?.next().ok_or_else(|| {
+        Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} could not be resolved", address),
+        )
+    })?;
+
+    let socket = Socket::new(
+        Domain::for_address(address),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+    socket.set_reuse_address(true)?;
+    socket.bind(&address.into())?;
+    // Clamp the backlog to the max value that fits in the `c_int` expected by listen()
+    socket.listen(backlog.min(i32::MAX as u32) as i32)?;
+
+    Ok(TcpListener::from(socket))
+}
+
+/// Build a connection pool that will only try to establish connections when needed.
+///
+/// sqlx::PgPool is built around sqlx::PgConnection to handle multiple concurrent
+/// queries through a connection pool.
+pub fn get_connection_pool(configurations: &DatabaseConfigurations) -> PgPool {
+    PgPoolOptions::new().connect_lazy_with(configurations.with_db())
+}
+
+/// Time to wait before trying again to get ready, see [get_ready].
+const GET_READY_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Warm up the dependencies and run the startup jobs, then mark the instance as
+/// ready.
+///
+/// It retries until it succeeds: if the database is unreachable, the instance keeps
+/// answering liveness probes but stays out of rotation until the database is back,
+/// rather than crashing in a loop.
+async fn get_ready(
+    pool: PgPool,
+    readiness: web::Data<Readiness>,
+    cipher: web::Data<Option<PiiCipher>>,
+) {
+    loop {
+        match prepare(&pool, cipher.get_ref().as_ref()).await {
+            Ok(()) => break,
+            Err(e) => {
+                tracing::error!("Failed to get ready, retrying: {}", e);
+                actix_web::rt::time::sleep(GET_READY_RETRY_INTERVAL).await;
+            }
+        }
+    }
+    readiness.mark_ready();
+
+    // Bring rows stored in plaintext or with a previous key up to date in the
+    // background, so that rotating keys only takes a configuration change.
+    // Replicas starting together would step on each other's toes, so only
+    // one of them runs the job.
+    if let Some(cipher) = cipher.as_ref() {
+        let job = run_exclusively(&pool, keys::REENCRYPT_SUBSCRIBERS, || {
+            reencrypt_subscribers(&pool, cipher)
+        });
+        if let Err(e) = job.await {
+            tracing::error!(
+                "Failed to re-encrypt subscribers: {}",
+                redact_database_error(&e)
+            );
+        }
+    }
+}
+
+/// Everything that must complete before the instance takes traffic.
+async fn prepare(pool: &PgPool, cipher: Option<&PiiCipher>) -> Result<(), Error> {
+    warm_up(pool).await?;
+    // Encrypted emails are only unique through their blind index, so every
+    // plaintext row needs one before the instance takes any sign-up
+    if let Some(cipher) = cipher {
+        backfill_blind_indexes(pool, cipher)
+            .await
+            .map_err(|e| Error::other(redact_database_error(&e)))?;
+    }
+
+    Ok(())
+}
+
+/// Eagerly establish a connection with every dependency of the application.
+///
+/// The pool is lazy, so without this step the first request routed to a fresh
+/// instance would pay for the connection handshake (or fail if the database is
+/// unreachable). Running a trivial query also makes the pool keep that connection
+/// open for later use.
+#[tracing::instrument(name = "Warming up application dependencies", skip(pool))]
+async fn warm_up(pool: &PgPool) -> Result<(), Error> {
+    sqlx::query("SELECT 1").execute(pool).await.map_err(|e| {
+        tracing::error!("Failed to warm up the connection pool: {:?}", e);
+        Error::other(e)
+    })?;
+
+    Ok(())
+}
 
 /// Create a [Server] and return [Result] to be handled by main().
 ///
@@ -16,13 +202,29 @@ use crate::routes::{health_check, subscribe};
 /// ports without conflicts. To run the app in tests, a listener will define the address
 /// where the app will be running with a random available port. Importantly, it'll provide
 /// a way to retrieve the selected port to perform the actual validation.
-pub fn run(listener: TcpListener, db_pool: PgPool) -> Result<Server, Error> {
+pub fn run(
+    listener: TcpListener,
+    db_pool: PgPool,
+    readiness: web::Data<Readiness>,
+    settings: &ApplicationConfigurations,
+    subscriptions: SubscriptionsConfigurations,
+    cipher: web::Data<Option<PiiCipher>>,
+) -> Result<Server, Error> {
     // actix-web's runtime model spin up a worker process for each available core
     // on the machine. Each worker runs its own copy of the app. Because of this,
     // HttpServer::new expect a cloneable instance of connection, so we need
     // to wrap it in an Arc in an Arc smart pointer. In this case, however, we're
     // using web::Data, which boils down to an Arc.
     let db_pool = web::Data::new(db_pool);
+    let subscriptions = web::Data::new(subscriptions);
+    let subscribe_timeout = settings.timeouts.subscribe();
+    // Shared by all the workers, see LoadShedding
+    let load_shedding = LoadShedding::new(
+        settings.load_shedding.min_in_flight,
+        settings.load_shedding.max_in_flight,
+        settings.load_shedding.target_latency(),
+    );
+    let trusted_proxies = TrustedProxies::new(settings.trusted_proxies.clone());
 
     // HttpServer handles all "transport level" concerns.
     // First, establishes a connection with a client of the API. Then, an App
@@ -30,23 +232,56 @@ pub fn run(listener: TcpListener, db_pool: PgPool) -> Result<Server, Error> {
     // request handlers, etc). App takes a request as input and spit out a
     // response. App implements the "builder pattern". This allows us to chain
     // method calls one after the other to add features to the same App instance.
-    let server = HttpServer::new(move || {
+    let mut server = HttpServer::new(move || {
         App::new()
             // wrap() allows us to pass middlewares. TracingLogger is a
             // tracing-based logger (as a replacement for log-based middlewares::Logger).
             // This is required to easily add a request_id and other useful information
             // to the logs
             .wrap(TracingLogger)
-            .route("/health_check", web::get().to(health_check))
-            .route("/subscriptions", web::post().to(subscribe))
+            // Registered last, so it's the outermost middleware: forwarded headers
+            // from untrusted peers are gone before anybody (including the logger)
+            // looks at them
+            .wrap(trusted_proxies.clone())
+            // Every resource falls back to method_not_allowed() when none of its
+            // routes matches the request method, while the App falls back to
+            // not_found() when none of the resources matches the request path.
+            .service(
+                web::resource("/health_check")
+                    .route(web::get().to(health_check))
+                    .default_service(method_not_allowed(&[Method::GET])),
+            )
+            .service(
+                web::resource("/health_check/ready")
+                    .route(web::get().to(readiness_check))
+                    .default_service(method_not_allowed(&[Method::GET])),
+            )
+            .service(
+                web::resource("/subscriptions")
+                    // The last registered middleware is the outermost one: requests
+                    // are shed before spending any time budget
+                    .wrap(Timeout::new(subscribe_timeout))
+                    .wrap(load_shedding.clone())
+                    .route(web::post().to(subscribe))
+                    .default_service(method_not_allowed(&[Method::POST])),
+            )
+            .default_service(web::route().to(not_found))
             // Register the connection pool as part of the application state
             // (later on accessible through actix_web::web::Data extractor
             // inside every route). We can use .data() and app_data(). The former
             // would add another Arc pointer on top of the existing one.
             .app_data(db_pool.clone())
+            .app_data(readiness.clone())
+            .app_data(subscriptions.clone())
+            .app_data(cipher.clone())
     })
-    .listen(listener)?
-    .run();
+    .keep_alive(settings.server.keep_alive)
+    .client_timeout(settings.server.client_request_timeout);
+
+    // Leave actix-web's default (one worker per physical core) when unset
+    if let Some(workers) = settings.server.workers {
+        server = server.workers(workers);
+    }
 
-    Ok(server)
+    Ok(server.listen(listener)?.run())
 }
@@ -1,8 +1,19 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    RwLock,
+};
+
+use hmac::{Hmac, Mac, NewMac};
+use lazy_static::lazy_static;
+use sha2::Sha256;
+use sqlx::postgres::PgDatabaseError;
 use tracing::{subscriber::set_global_default, Subscriber};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
 use tracing_subscriber::{prelude::__tracing_subscriber_SubscriberExt, EnvFilter, Registry};
 
+use crate::configuration::TelemetryConfigurations;
+
 /// Compose multiple layers into a `tracing`'s subscriber.
 ///
 /// ### Implementation Notes
@@ -46,3 +57,140 @@ pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
     // Specify what subscriber should be used to process spans
     set_global_default(subscriber).expect("Failed to set subscriber.");
 }
+
+/// Whether personally identifiable data is redacted in logs, see [redact].
+static REDACT_PII: AtomicBool = AtomicBool::new(true);
+
+lazy_static! {
+    /// Key of the HMAC used by [redact]. Random until a key is configured.
+    static ref REDACTION_KEY: RwLock<Vec<u8>> =
+        RwLock::new(rand::random::<[u8; 32]>().to_vec());
+}
+
+/// Apply the configured redaction of personally identifiable data in logs.
+///
+/// Redaction is on until this is called, so nothing leaks before the
+/// configurations are loaded.
+pub fn set_pii_redaction(configurations: &TelemetryConfigurations) -> Result<(), String> {
+    if let Some(key) = configurations.redaction_key()? {
+        *REDACTION_KEY.write().unwrap() = key;
+    }
+    REDACT_PII.store(configurations.redact_pii, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Render a personally identifiable value (e. g., an email or a name) for a span
+/// field or a log message.
+///
+/// When redaction is enabled, the value is replaced by a short keyed hash of it:
+/// logs about the same subscriber can still be correlated, but without the key
+/// the value can't be recovered, even by hashing lists of known emails.
+pub fn redact(value: &str) -> String {
+    if REDACT_PII.load(Ordering::Relaxed) {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&REDACTION_KEY.read().unwrap())
+            .expect("HMAC accepts keys of any length");
+        mac.update(value.as_bytes());
+        format!(
+            "[redacted:{}]",
+            hex::encode(&mac.finalize().into_bytes()[..6])
+        )
+    } else {
+        value.to_owned()
+    }
+}
+
+/// Redact, as [redact] does, every email address found in `text`.
+///
+/// Meant for messages we don't control, e. g., a Postgres error reporting the
+/// value that violated a unique constraint.
+pub fn redact_emails(text: &str) -> String {
+    // Characters that can't be part of an (unquoted) email address
+    let is_delimiter = |c: char| c.is_whitespace() || "\"'`()<>[]{},;:=\\".contains(c);
+
+    let mut redacted = String::with_capacity(text.len());
+    let mut word = String::new();
+    for c in text.chars().chain(std::iter::once(' ')) {
+        if is_delimiter(c) {
+            match word.split_once('@') {
+                Some((local, domain)) if !local.is_empty() && !domain.is_empty() => {
+                    redacted.push_str(&redact(&word))
+                }
+                _ => redacted.push_str(&word),
+            }
+            word.clear();
+            redacted.push(c);
+        } else {
+            word.push(c);
+        }
+    }
+    // Drop the delimiter we chained to flush the last word
+    redacted.pop();
+    redacted
+}
+
+/// Describe a database error for the logs, leaving out the values it may carry.
+///
+/// Postgres errors come with details such as `Failing row contains (...)` or
+/// `Key (email)=(...) already exists.`, which can include any personally
+/// identifiable data (names, custom fields, ...). When redaction is enabled, we
+/// only keep the error code and the constraint, which tell what went wrong.
+pub fn redact_database_error(e: &sqlx::Error) -> String {
+    if !REDACT_PII.load(Ordering::Relaxed) {
+        return format!("{:?}", e);
+    }
+
+    match e {
+        sqlx::Error::Database(e) => match e.try_downcast_ref::<PgDatabaseError>() {
+            Some(e) => format!(
+                "Database error {} (constraint: {})",
+                e.code(),
+                e.constraint().unwrap_or("none")
+            ),
+            None => format!(
+                "Database error {}",
+                e.code().as_deref().unwrap_or("without code")
+            ),
+        },
+        // Other errors are about the connection or the driver, not the data
+        e => redact_emails(&e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{redact, redact_emails, set_pii_redaction};
+    use crate::configuration::TelemetryConfigurations;
+
+    #[test]
+    fn redacted_values_are_unreadable_but_can_be_correlated() {
+        let redacted = redact("nick_bourbaki@gmail.com");
+
+        assert!(!redacted.contains("bourbaki"));
+        assert_eq!(redacted, redact("nick_bourbaki@gmail.com"));
+        assert_ne!(redacted, redact("ursula_le_guin@gmail.com"));
+    }
+
+    #[test]
+    fn short_redaction_keys_are_rejected() {
+        let configurations = TelemetryConfigurations {
+            redact_pii: true,
+            redaction_key: Some(base64::encode([1u8; 16])),
+        };
+
+        assert!(set_pii_redaction(&configurations).is_err());
+    }
+
+    #[test]
+    fn emails_in_error_messages_are_redacted() {
+        let message = r#"Key (email)=(nick_bourbaki@gmail.com) already exists."#;
+
+        assert_eq!(
+            redact_emails(message),
+            format!(
+                "Key (email)=({}) already exists.",
+                redact("nick_bourbaki@gmail.com")
+            )
+        );
+        assert_eq!(redact_emails("No emails @ all"), "No emails @ all");
+    }
+}
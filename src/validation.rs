@@ -0,0 +1,234 @@
+use std::{fmt, future::Future, pin::Pin};
+
+use actix_web::{
+    dev::Payload, error::InternalError, http::StatusCode, web, FromRequest, HttpRequest,
+    HttpResponse, ResponseError,
+};
+use serde::de::DeserializeOwned;
+
+use crate::routes::{error_response_with_fields, ErrorEnvelope};
+
+/// Message of the `400 BAD REQUEST` responses to invalid payloads.
+const MESSAGE: &str = "The request contains invalid fields.";
+
+/// A validation failure tied to a specific field of the payload.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+/// All the validation failures found while parsing a payload.
+///
+/// We collect every failure instead of bailing out on the first one, so the
+/// caller can fix all of them at once.
+#[derive(Debug, Default)]
+pub struct ValidationErrors(Vec<FieldError>);
+
+impl ValidationErrors {
+    pub fn add(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.0.push(FieldError {
+            field: field.into(),
+            message: message.into(),
+        });
+    }
+
+    /// Unwrap the value of a field that can't be omitted, recording a failure when
+    /// it's missing.
+    ///
+    /// Input fields are deserialised as [Option]s so that a missing one is reported
+    /// like any other invalid field, instead of failing the whole deserialisation.
+    pub fn require<T>(&mut self, field: &str, value: Option<T>) -> Option<T> {
+        if value.is_none() {
+            self.add(field, format!("{} is required.", field));
+        }
+        value
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Render the failures in the representation preferred by the caller, see
+    /// [error_response](crate::routes::error_response).
+    pub fn into_error(self, req: &HttpRequest) -> actix_web::Error {
+        let response = error_response_with_fields(req, StatusCode::BAD_REQUEST, MESSAGE, &self.0);
+        InternalError::from_response(self, response).into()
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields: Vec<_> = self.0.iter().map(|e| e.field.as_str()).collect();
+        write!(f, "Invalid values for fields: {}", fields.join(", "))
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+/// Render the failures as `400 BAD REQUEST` with the JSON error envelope. The
+/// extractors go through [ValidationErrors::into_error] instead, so that browsers
+/// get an HTML page.
+impl ResponseError for ValidationErrors {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        HttpResponse::build(status).json(ErrorEnvelope::new(status, MESSAGE).with_fields(&self.0))
+    }
+}
+
+/// Domain types that can be built out of a raw, deserialised payload.
+///
+/// [Validate::Input] is the _shape_ of the data as it arrives from the network (it
+/// only needs to be deserialisable), while `Self` is the domain type we get once
+/// every field went through its parsing function.
+///
+/// The [HttpRequest] gives access to the application state, for validation rules
+/// that depend on configuration.
+pub trait Validate: Sized {
+    type Input: DeserializeOwned;
+
+    fn validate(input: Self::Input, req: &HttpRequest) -> Result<Self, ValidationErrors>;
+}
+
+/// Extractor that deserialises an `application/x-www-form-urlencoded` body and
+/// validates it into `T`.
+///
+/// Deserialisation failures (e. g., a malformed body) are handled by [web::Form],
+/// while validation failures (including missing fields, see
+/// [ValidationErrors::require]) are returned as [ValidationErrors]. Either way, the
+/// handler is never invoked with invalid data.
+pub struct ValidatedForm<T>(pub T);
+
+/// Same as [ValidatedForm], but for `application/json` bodies.
+pub struct ValidatedJson<T>(pub T);
+
+impl<T> ValidatedForm<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> ValidatedJson<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> FromRequest for ValidatedForm<T>
+where
+    T: Validate + 'static,
+{
+    type Config = ();
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let form = web::Form::<T::Input>::from_request(req, payload);
+        let req = req.clone();
+        Box::pin(async move {
+            let input = form.await?.into_inner();
+            T::validate(input, &req)
+                .map(Self)
+                .map_err(|e| e.into_error(&req))
+        })
+    }
+}
+
+impl<T> FromRequest for ValidatedJson<T>
+where
+    T: Validate + 'static,
+{
+    type Config = ();
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let json = web::Json::<T::Input>::from_request(req, payload);
+        let req = req.clone();
+        Box::pin(async move {
+            let input = json.await?.into_inner();
+            T::validate(input, &req)
+                .map(Self)
+                .map_err(|e| e.into_error(&req))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test, web, App, HttpResponse};
+
+    use super::{Validate, ValidatedJson, ValidationErrors};
+
+    struct Name(String);
+
+    #[derive(serde::Deserialize)]
+    struct NameInput {
+        name: Option<String>,
+    }
+
+    impl Validate for Name {
+        type Input = NameInput;
+
+        fn validate(
+            input: NameInput,
+            _: &actix_web::HttpRequest,
+        ) -> Result<Self, ValidationErrors> {
+            let mut errors = ValidationErrors::default();
+            match errors.require("name", input.name) {
+                Some(name) if name.trim().is_empty() => {
+                    errors.add("name", "Name cannot be empty.");
+                    Err(errors)
+                }
+                Some(name) => Ok(Self(name)),
+                None => Err(errors),
+            }
+        }
+    }
+
+    async fn greet(name: ValidatedJson<Name>) -> HttpResponse {
+        HttpResponse::Ok().body(format!("Hello, {}!", name.into_inner().0))
+    }
+
+    #[actix_rt::test]
+    async fn valid_json_payloads_reach_the_handler() {
+        let app = test::init_service(App::new().route("/", web::post().to(greet))).await;
+        let request = test::TestRequest::post()
+            .uri("/")
+            .set_json(&serde_json::json!({ "name": "Ursula" }))
+            .to_request();
+
+        let body = test::read_response(&app, request).await;
+
+        assert_eq!(body, "Hello, Ursula!");
+    }
+
+    #[actix_rt::test]
+    async fn invalid_json_payloads_are_rejected_with_field_errors() {
+        let app = test::init_service(App::new().route("/", web::post().to(greet))).await;
+
+        let test_cases = vec![
+            (serde_json::json!({}), "name is required."),
+            (serde_json::json!({ "name": " " }), "Name cannot be empty."),
+        ];
+
+        for (payload, message) in test_cases {
+            let request = test::TestRequest::post()
+                .uri("/")
+                .set_json(&payload)
+                .to_request();
+            let response = test::call_service(&app, request).await;
+
+            assert_eq!(response.status().as_u16(), 400);
+            let body: serde_json::Value = test::read_body_json(response).await;
+            assert_eq!(
+                body["error"]["fields"],
+                serde_json::json!([{ "field": "name", "message": message }])
+            );
+        }
+    }
+}
@@ -0,0 +1,86 @@
+mod helpers;
+
+use std::time::{Duration, Instant};
+
+use helpers::spawn_app;
+use sqlx::Connection;
+use zero2prod::advisory_lock::run_exclusively;
+use zero2prod::check::check_migrations;
+use zero2prod::db::begin_with_deadline;
+use zero2prod::middleware::Deadline;
+
+#[actix_rt::test]
+async fn singleton_jobs_are_skipped_while_another_replica_runs_them() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let key = 42;
+    // Another replica holding the lock
+    let mut leader = test_app
+        .db_pool
+        .acquire()
+        .await
+        .expect("Failed to acquire a connection.");
+    sqlx::query("SELECT pg_advisory_lock($1)")
+        .bind(key)
+        .execute(&mut leader)
+        .await
+        .expect("Failed to acquire the lock.");
+
+    // Act
+    let while_locked = run_exclusively(&test_app.db_pool, key, || async { Ok(()) }).await;
+    // The leader dies
+    leader.detach().close().await.unwrap();
+    let after_takeover = run_exclusively(&test_app.db_pool, key, || async { Ok(()) }).await;
+
+    // Assert
+    assert_eq!(while_locked.expect("Failed to run the job."), None);
+    assert_eq!(after_takeover.expect("Failed to run the job."), Some(()));
+}
+
+#[actix_rt::test]
+async fn check_migrations_reports_pending_migrations() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let up_to_date = check_migrations(&test_app.db_pool).await;
+
+    // Act
+    // sqlx's bookkeeping table isn't part of our schema, so it isn't checked at
+    // compile time
+    let (last,): (i64,) = sqlx::query_as(
+        r#"
+        DELETE FROM _sqlx_migrations
+        WHERE version = (SELECT MAX(version) FROM _sqlx_migrations)
+        RETURNING version
+        "#,
+    )
+    .fetch_one(&test_app.db_pool)
+    .await
+    .expect("Failed to forget the last migration.");
+    let pending = check_migrations(&test_app.db_pool).await;
+
+    // Assert
+    assert!(up_to_date.is_ok());
+    assert_eq!(pending, Err(format!("pending: {}", last)));
+}
+
+#[actix_rt::test]
+async fn transactions_stop_running_statements_past_their_deadline() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let mut transaction = begin_with_deadline(
+        &test_app.db_pool,
+        Deadline::after(Duration::from_millis(200)),
+    )
+    .await
+    .expect("Failed to begin transaction.");
+
+    // Act
+    let started = Instant::now();
+    let result = sqlx::query("SELECT pg_sleep(5)")
+        .execute(&mut transaction)
+        .await;
+
+    // Assert
+    assert!(result.is_err());
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
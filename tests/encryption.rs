@@ -0,0 +1,78 @@
+mod helpers;
+
+use helpers::{encryption_configurations, new_subscriber, spawn_app};
+use zero2prod::encryption::{backfill_blind_indexes, reencrypt_subscribers, PiiCipher, PiiColumn};
+use zero2prod::routes::insert_subscriber;
+
+#[actix_rt::test]
+async fn reencryption_skips_the_subscribers_it_cannot_decrypt() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let cipher = PiiCipher::from_configurations(&encryption_configurations())
+        .expect("Failed to build cipher.");
+    // A plaintext name that looks like a ciphertext
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &test_app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("name=enc%3Av1%3Ax%3AAAAA&email=nick_bourbaki%40gmail.com")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(200, response.status().as_u16());
+    // Encrypted with a key that is no longer configured
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, encryption_key_id)
+        VALUES ($1, 'enc:v1:retired:AAAA', 'enc:v1:retired:AAAA', now(), 'retired')
+        "#,
+        uuid::Uuid::new_v4()
+    )
+    .execute(&test_app.db_pool)
+    .await
+    .expect("Failed to insert subscription.");
+
+    // Act
+    let updated = reencrypt_subscribers(&test_app.db_pool, &cipher).await;
+
+    // Assert
+    assert_eq!(updated.expect("Failed to re-encrypt subscribers."), 1);
+    let saved = sqlx::query!(
+        "SELECT id, name, encryption_key_id FROM subscriptions WHERE email_blind_index = $1",
+        cipher.blind_index("nick_bourbaki@gmail.com")
+    )
+    .fetch_one(&test_app.db_pool)
+    .await
+    .expect("Failed to fetch saved subscription");
+    assert_eq!(saved.encryption_key_id.as_deref(), Some("2021-04"));
+    assert_eq!(
+        cipher
+            .decrypt(&saved.name, saved.id, PiiColumn::Name)
+            .unwrap(),
+        "enc:v1:x:AAAA"
+    );
+}
+
+#[actix_rt::test]
+async fn encrypted_subscribers_cannot_duplicate_plaintext_ones() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let cipher = PiiCipher::from_configurations(&encryption_configurations())
+        .expect("Failed to build cipher.");
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &test_app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("name=nicolas%20bourbaki&email=nick_bourbaki%40gmail.com")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(200, response.status().as_u16());
+
+    // Act
+    let backfilled = backfill_blind_indexes(&test_app.db_pool, &cipher).await;
+    let mut transaction = test_app.db_pool.begin().await.unwrap();
+    let inserted = insert_subscriber(&mut transaction, &new_subscriber(), Some(&cipher)).await;
+
+    // Assert
+    assert_eq!(backfilled.expect("Failed to backfill blind indexes."), 1);
+    assert!(inserted.is_err());
+}
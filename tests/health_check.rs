@@ -1,93 +1,9 @@
-use std::net::TcpListener;
-
-use lazy_static::lazy_static;
-use sqlx::{Connection, Executor, PgConnection, PgPool};
-use uuid::Uuid;
-
-use zero2prod::configuration::{get_configurations, DatabaseConfigurations};
-use zero2prod::startup::run;
-use zero2prod::telemetry::{get_subscriber, init_subscriber};
-
-// Ensure that the tracing stack is only initialised once.
-lazy_static! {
-    static ref TRACING: () = {
-        // If TEST_LOG is set, pick all the spans that are at least debug-level,
-        // otherwise, we drop everithing by passing an empty filter.
-        let filter = if std::env::var("TEST_LOG").is_ok() {
-            "debug"
-        } else {
-            ""
-        };
-        let subscriber = get_subscriber("test".into(), filter.into());
-        init_subscriber(subscriber);
-    };
-}
-
-pub struct TestApp {
-    pub address: String,
-    pub db_pool: PgPool,
-}
-
-// Launch application in the background
-async fn spawn_app() -> TestApp {
-    // Set up tracing stack.
-    // The first time initialize is invoked the code in TRACING is executed.
-    // All other invocations will instead skip execution.
-    lazy_static::initialize(&TRACING);
-
-    // A port = 0 means that the SO will automatically scan for a random available port
-    // to run the server. This allows us to avoid conflicts and run multiples tests
-    // concurrently. A TcpListener is used to define the address and then return it to
-    // be used by the HTTP client performing the call.
-    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind random port");
-
-    let port = listener.local_addr().unwrap().port();
-    let address = format!("http://127.0.0.1:{}", port);
-
-    let mut configurations = get_configurations().expect("Failed to read configurations.");
-    configurations.database.database_name = Uuid::new_v4().to_string();
-    let connection_pool = configure_database(&configurations.database).await;
-
-    let server = run(listener, connection_pool.clone()).expect("Failed to bind address.");
-
-    // Launch the server as a background task. tokio::spawn returns a handle to the
-    // spawned future (althought we have no use for it here)
-    tokio::spawn(server);
-
-    TestApp {
-        address,
-        db_pool: connection_pool,
-    }
-}
+mod helpers;
 
-// Before each test we
-// (i) create a new logical database with a unique name and
-// (ii) run database migration on it.
-//
-// This is required to avoid using the same database connection for
-// all the test. That is, we need to isolate the test to be able able
-// to run it in a determistic way.
-pub async fn configure_database(config: &DatabaseConfigurations) -> PgPool {
-    // Create database
-    let mut connection = PgConnection::connect_with(&config.without_db())
-        .await
-        .expect("Failed to connect to Postgres");
-    connection
-        .execute(&*format!(r#"CREATE DATABASE "{}";"#, config.database_name))
-        .await
-        .expect("Failed to create database.");
+use std::{io::Write, net::TcpStream, time::Duration};
 
-    // Migrate database
-    let connection_pool = PgPool::connect_with(config.with_db())
-        .await
-        .expect("Failed to connect to Postgres.");
-    sqlx::migrate!("./migrations") // Same macro used by sqlx-cli when executing sqlx migrate run
-        .run(&connection_pool)
-        .await
-        .expect("Failed to migrate the database.");
-
-    connection_pool
-}
+use helpers::{encryption_configurations, spawn_app, spawn_app_with, spawn_app_without_database};
+use zero2prod::configuration::{CustomFieldConfigurations, CustomFieldType};
 
 #[actix_rt::test]
 async fn subscribe_returns_a_200_for_valid_form_data() {
@@ -98,7 +14,7 @@ async fn subscribe_returns_a_200_for_valid_form_data() {
 
     // Act
     let response = client
-        .post(&format!("{}/subscriptions", &test_app.address))
+        .post(format!("{}/subscriptions", &test_app.address))
         .header("Content-Type", "application/x-www-form-urlencoded")
         .body(body)
         .send()
@@ -122,6 +38,168 @@ async fn subscribe_returns_a_200_for_valid_form_data() {
     assert_eq!(saved.name, "nicolas bourbaki");
 }
 
+#[actix_rt::test]
+async fn subscribe_persists_the_configured_custom_fields() {
+    // Arrange
+    let test_app = spawn_app_with(|c| {
+        c.subscriptions.custom_fields = vec![CustomFieldConfigurations {
+            name: "company".into(),
+            kind: CustomFieldType::String,
+            required: true,
+        }]
+    })
+    .await;
+    let client = reqwest::Client::new();
+    let body = "name=nicolas%20bourbaki&email=nick_bourbaki%40gmail.com&company=ENS&submit=go";
+
+    // Act
+    let response = client
+        .post(format!("{}/subscriptions", &test_app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT custom_fields FROM subscriptions")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+    assert_eq!(saved.custom_fields, serde_json::json!({ "company": "ENS" }));
+}
+
+#[actix_rt::test]
+async fn subscribe_returns_a_400_when_a_required_custom_field_is_missing() {
+    // Arrange
+    let test_app = spawn_app_with(|c| {
+        c.subscriptions.custom_fields = vec![CustomFieldConfigurations {
+            name: "company".into(),
+            kind: CustomFieldType::String,
+            required: true,
+        }]
+    })
+    .await;
+    let client = reqwest::Client::new();
+    let body = "name=nicolas%20bourbaki&email=nick_bourbaki%40gmail.com";
+
+    // Act
+    let response = client
+        .post(format!("{}/subscriptions", &test_app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+    let body = response.text().await.expect("Failed to read body.");
+    assert!(body.contains(r#""field":"company""#));
+}
+
+#[actix_rt::test]
+async fn subscribe_persists_referral_and_utm_parameters() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let client = reqwest::Client::new();
+    let body = "name=nicolas%20bourbaki&email=nick_bourbaki%40gmail.com&utm_source=newsletter";
+
+    // Act
+    let response = client
+        .post(format!(
+            "{}/subscriptions?ref=alice&utm_source=twitter&utm_campaign=launch",
+            &test_app.address
+        ))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!(
+        "SELECT referrer, utm_source, utm_medium, utm_campaign, custom_fields FROM subscriptions"
+    )
+    .fetch_one(&test_app.db_pool)
+    .await
+    .expect("Failed to fetch saved subscription");
+    assert_eq!(saved.referrer.as_deref(), Some("alice"));
+    // Form fields take precedence over the query string
+    assert_eq!(saved.utm_source.as_deref(), Some("newsletter"));
+    assert_eq!(saved.utm_medium, None);
+    assert_eq!(saved.utm_campaign.as_deref(), Some("launch"));
+    assert_eq!(saved.custom_fields, serde_json::json!({}));
+}
+
+#[actix_rt::test]
+async fn subscribe_encrypts_personal_data_at_rest_when_configured() {
+    // Arrange
+    let test_app = spawn_app_with(|c| c.encryption = Some(encryption_configurations())).await;
+    let client = reqwest::Client::new();
+    let body = "name=nicolas%20bourbaki&email=nick_bourbaki%40gmail.com";
+
+    // Act
+    let mut statuses = Vec::new();
+    for _ in 0..2 {
+        let response = client
+            .post(format!("{}/subscriptions", &test_app.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.");
+        statuses.push(response.status().as_u16());
+    }
+
+    // Assert
+    // The blind index keeps enforcing the uniqueness of emails
+    assert_eq!(statuses, [200, 500]);
+
+    let saved =
+        sqlx::query!("SELECT email, name, email_blind_index, encryption_key_id FROM subscriptions")
+            .fetch_one(&test_app.db_pool)
+            .await
+            .expect("Failed to fetch saved subscription");
+    assert!(saved.email.starts_with("enc:v1:2021-04:"));
+    assert!(!saved.email.contains("bourbaki"));
+    assert!(saved.name.starts_with("enc:v1:2021-04:"));
+    assert!(saved.email_blind_index.is_some());
+    assert_eq!(saved.encryption_key_id.as_deref(), Some("2021-04"));
+}
+
+#[actix_rt::test]
+async fn subscribe_persists_the_subscription_source() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let client = reqwest::Client::new();
+    let body = "name=nicolas%20bourbaki&email=nick_bourbaki%40gmail.com&source=widget";
+
+    // Act
+    let response = client
+        .post(format!("{}/subscriptions", &test_app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT source, custom_fields FROM subscriptions")
+        .fetch_one(&test_app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+    assert_eq!(saved.source.as_deref(), Some("widget"));
+    // It's not mistaken for a custom field
+    assert_eq!(saved.custom_fields, serde_json::json!({}));
+}
+
 // This is an example of table-driven test (aka parametrised test). It is particularly
 // helpful when dealing with bad inputs - instead of duplicating test logic several
 // times we can simply run the same assertion against a collection of known invalid
@@ -132,15 +210,23 @@ async fn subscribe_returns_a_400_when_data_is_missing() {
     let test_app = spawn_app().await;
     let client = reqwest::Client::new();
     let test_cases = vec![
-        ("name=nicolas%20bourbaki", "missing the email"),
-        ("email=nick_bourbaki%40gmail.com", "missing the name"),
-        ("", "missing both name and email"),
+        (
+            "name=nicolas%20bourbaki",
+            vec!["email"],
+            "missing the email",
+        ),
+        (
+            "email=nick_bourbaki%40gmail.com",
+            vec!["name"],
+            "missing the name",
+        ),
+        ("", vec!["email", "name"], "missing both name and email"),
     ];
 
-    for (invalid_body, error_message) in test_cases {
+    for (invalid_body, fields, error_message) in test_cases {
         // Act
         let response = client
-            .post(&format!("{}/subscriptions", &test_app.address))
+            .post(format!("{}/subscriptions", &test_app.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(invalid_body)
             .send()
@@ -154,9 +240,168 @@ async fn subscribe_returns_a_400_when_data_is_missing() {
             "The API did not fail with 400 Bad Request when the payload was {}.",
             error_message
         );
+        let body = response.text().await.expect("Failed to read body.");
+        for field in fields {
+            assert!(
+                body.contains(&format!(
+                    r#""field":"{0}","message":"{0} is required.""#,
+                    field
+                )),
+                "The API did not report the missing {} when the payload was {}.",
+                field,
+                error_message
+            );
+        }
     }
 }
 
+#[actix_rt::test]
+async fn subscribe_returns_a_400_html_page_for_browsers_when_data_is_invalid() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &test_app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Accept", "text/html,application/xhtml+xml")
+        .body("name=nicolas%20bourbaki&email=%3Cscript%3E")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+    assert!(response
+        .headers()
+        .get("Content-Type")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .starts_with("text/html"));
+    let body = response.text().await.expect("Failed to read body.");
+    assert!(body.contains("<strong>email</strong>"));
+    // The submitted value is echoed back escaped
+    assert!(body.contains("&lt;script&gt;"));
+    assert!(!body.contains("<script>"));
+}
+
+#[actix_rt::test]
+async fn subscribe_returns_a_400_when_fields_are_present_but_invalid() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let client = reqwest::Client::new();
+    let test_cases = vec![
+        (
+            "name=&email=nick_bourbaki%40gmail.com",
+            "name",
+            "empty name",
+        ),
+        ("name=Nicolas&email=", "email", "empty email"),
+        (
+            "name=Nicolas&email=definitely-not-an-email",
+            "email",
+            "invalid email",
+        ),
+        (
+            "name=Nicolas&email=nick_bourbaki%40gmail.com&source=newsletter",
+            "source",
+            "unknown source",
+        ),
+    ];
+
+    for (body, field, description) in test_cases {
+        // Act
+        let response = client
+            .post(format!("{}/subscriptions", &test_app.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not return a 400 Bad Request when the payload was {}.",
+            description
+        );
+        let body = response.text().await.expect("Failed to read body.");
+        assert!(
+            body.contains(&format!(r#""field":"{}""#, field)),
+            "The API did not report the invalid field when the payload was {}.",
+            description
+        );
+    }
+}
+
+#[actix_rt::test]
+async fn subscribe_returns_a_504_when_the_handler_times_out() {
+    // Arrange
+    let test_app = spawn_app_with(|c| c.application.timeouts.subscribe_milliseconds = 0).await;
+    let client = reqwest::Client::new();
+    let body = "name=nicolas%20bourbaki&email=nick_bourbaki%40gmail.com";
+
+    // Act
+    let response = client
+        .post(format!("{}/subscriptions", &test_app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(504, response.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn subscribe_returns_a_503_when_the_server_is_saturated() {
+    // Arrange
+    let test_app = spawn_app_with(|c| {
+        c.application.load_shedding.min_in_flight = 1;
+        c.application.load_shedding.max_in_flight = 1;
+    })
+    .await;
+    let client = reqwest::Client::new();
+    let body = "name=nicolas%20bourbaki&email=nick_bourbaki%40gmail.com";
+    // A request whose body never arrives stays in flight
+    let mut stalled = TcpStream::connect(test_app.address.trim_start_matches("http://"))
+        .expect("Failed to connect.");
+    stalled
+        .write_all(
+            b"POST /subscriptions HTTP/1.1\r\n\
+            Host: localhost\r\n\
+            Content-Type: application/x-www-form-urlencoded\r\n\
+            Content-Length: 100\r\n\r\n\
+            name=",
+        )
+        .expect("Failed to send the request head.");
+    // Give the server the time to start processing it
+    actix_rt::time::sleep(Duration::from_millis(200)).await;
+
+    // Act
+    let response = client
+        .post(format!("{}/subscriptions", &test_app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(503, response.status().as_u16());
+    assert!(response.headers().contains_key("Retry-After"));
+    // Liveness must not be affected by load shedding
+    let response = client
+        .get(format!("{}/health_check", &test_app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert!(response.status().is_success());
+    drop(stalled);
+}
+
 // `actix_rt::test` is the testing equivalent of `actix_web::main`
 #[actix_rt::test]
 async fn health_check_works() {
@@ -167,7 +412,7 @@ async fn health_check_works() {
 
     // Act
     let response = client
-        .get(&format!("{}/health_check", &test_app.address))
+        .get(format!("{}/health_check", &test_app.address))
         .send()
         .await
         .expect("Failed to execute request.");
@@ -176,3 +421,144 @@ async fn health_check_works() {
     assert!(response.status().is_success());
     assert_eq!(Some(0), response.content_length());
 }
+
+#[actix_rt::test]
+async fn readiness_check_returns_503_while_warming_up() {
+    // Arrange
+    let address = spawn_app_without_database().await;
+    let client = reqwest::Client::new();
+
+    // Act
+    let liveness = client
+        .get(format!("{}/health_check", &address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    let readiness = client
+        .get(format!("{}/health_check/ready", &address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(200, liveness.status().as_u16());
+    assert_eq!(503, readiness.status().as_u16());
+}
+
+#[actix_rt::test]
+async fn subscribe_returns_a_503_while_warming_up() {
+    // Arrange
+    // Requests that bypass the readiness probe still reach the instance
+    let address = spawn_app_without_database().await;
+    let client = reqwest::Client::new();
+
+    // Act
+    let response = client
+        .post(format!("{}/subscriptions", &address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("name=nicolas%20bourbaki&email=nick_bourbaki%40gmail.com")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(503, response.status().as_u16());
+    assert_eq!(
+        response
+            .headers()
+            .get("Retry-After")
+            .and_then(|value| value.to_str().ok()),
+        Some("1")
+    );
+}
+
+#[actix_rt::test]
+async fn readiness_check_returns_200_once_warmed_up() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    // Act
+    let response = client
+        .get(format!("{}/health_check/ready", &test_app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(Some(0), response.content_length());
+}
+
+#[actix_rt::test]
+async fn unknown_routes_return_a_404_json_envelope_for_api_clients() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    // Act
+    let response = client
+        .get(format!("{}/not_a_route", &test_app.address))
+        .header("Accept", "application/json")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(404, response.status().as_u16());
+    assert_eq!(
+        Some("application/json"),
+        response
+            .headers()
+            .get("Content-Type")
+            .and_then(|value| value.to_str().ok())
+    );
+    let body = response.text().await.expect("Failed to read body.");
+    assert!(body.contains(r#""status":404"#));
+}
+
+#[actix_rt::test]
+async fn unknown_routes_return_a_404_html_page_for_browsers() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    // Act
+    let response = client
+        .get(format!("{}/not_a_route", &test_app.address))
+        .header("Accept", "text/html,application/xhtml+xml,*/*;q=0.8")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(404, response.status().as_u16());
+    let body = response.text().await.expect("Failed to read body.");
+    assert!(body.contains("<h1>404 Not Found</h1>"));
+}
+
+#[actix_rt::test]
+async fn unsupported_methods_return_a_405() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    // Act
+    let response = client
+        .get(format!("{}/subscriptions", &test_app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(405, response.status().as_u16());
+    assert_eq!(
+        response
+            .headers()
+            .get("Allow")
+            .and_then(|allow| allow.to_str().ok()),
+        Some("POST")
+    );
+    let body = response.text().await.expect("Failed to read body.");
+    assert!(body.contains(r#""status":405"#));
+}
@@ -0,0 +1,277 @@
+//! Helpers shared by the integration test crates.
+//!
+//! Every file in `tests/` is compiled as its own crate, and not all of them use
+//! every helper.
+#![allow(dead_code)]
+
+use std::{
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    process::Command,
+    time::Duration,
+};
+
+use lazy_static::lazy_static;
+use sqlx::{Connection, Executor, PgConnection, PgPool};
+use uuid::Uuid;
+
+use zero2prod::configuration::{
+    get_configurations, Configurations, DatabaseConfigurations, EncryptionConfigurations,
+};
+use zero2prod::domain::{
+    Attribution, CustomFields, NewSubscriber, SubscriberEmail, SubscriberName,
+};
+use zero2prod::startup::Application;
+use zero2prod::telemetry::{get_subscriber, init_subscriber};
+
+// Ensure that the tracing stack is only initialised once.
+lazy_static! {
+    static ref TRACING: () = {
+        // If TEST_LOG is set, pick all the spans that are at least debug-level,
+        // otherwise, we drop everithing by passing an empty filter.
+        let filter = if std::env::var("TEST_LOG").is_ok() {
+            "debug"
+        } else {
+            ""
+        };
+        let subscriber = get_subscriber("test".into(), filter.into());
+        init_subscriber(subscriber);
+    };
+
+    // Port of the Postgres container backing the tests when the configured database
+    // is unreachable, so that `cargo test` works without starting one by hand.
+    static ref POSTGRES_CONTAINER_PORT: Option<u16> = {
+        let configurations = get_configurations().expect("Failed to read configurations.");
+        let database = &configurations.database;
+        if is_reachable(&database.host, database.port) {
+            None
+        } else {
+            Some(start_postgres_container(database))
+        }
+    };
+}
+
+/// Label of the Postgres container started by [start_postgres_container].
+const POSTGRES_CONTAINER_LABEL: &str = "zero2prod-tests";
+
+pub struct TestApp {
+    pub address: String,
+    pub db_pool: PgPool,
+}
+
+// Launch application in the background
+pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+// Launch application in the background, tweaking the configurations first
+// (e. g., to exercise a code path that depends on a specific setting)
+pub async fn spawn_app_with(customise: impl FnOnce(&mut Configurations)) -> TestApp {
+    // Set up tracing stack.
+    // The first time initialize is invoked the code in TRACING is executed.
+    // All other invocations will instead skip execution.
+    lazy_static::initialize(&TRACING);
+
+    let mut configurations = get_configurations().expect("Failed to read configurations.");
+    // Use a different database for each test case
+    configurations.database.database_name = Uuid::new_v4().to_string();
+    // A port = 0 means that the SO will automatically scan for a random available port
+    // to run the server. This allows us to avoid conflicts and run multiples tests
+    // concurrently. The port is then retrieved from the built Application to be used
+    // by the HTTP client performing the call.
+    configurations.application.port = 0;
+    if let Some(port) = *POSTGRES_CONTAINER_PORT {
+        configurations.database.host = "127.0.0.1".into();
+        configurations.database.port = port;
+    }
+    customise(&mut configurations);
+
+    let connection_pool = configure_database(&configurations.database).await;
+
+    let application = Application::build(configurations)
+        .await
+        .expect("Failed to build application.");
+    let address = format!("http://127.0.0.1:{}", application.port());
+
+    // Launch the server as a background task. tokio::spawn returns a handle to the
+    // spawned future (althought we have no use for it here)
+    tokio::spawn(application.run_until_stopped());
+    wait_until_ready(&address).await;
+
+    TestApp {
+        address,
+        db_pool: connection_pool,
+    }
+}
+
+// Launch application in the background against an unreachable database, so that
+// it never gets past warming up. Returns its address.
+pub async fn spawn_app_without_database() -> String {
+    lazy_static::initialize(&TRACING);
+
+    let mut configurations = get_configurations().expect("Failed to read configurations.");
+    configurations.application.port = 0;
+    // Nothing listens on a port we just released
+    configurations.database.host = "127.0.0.1".into();
+    configurations.database.port = TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("Failed to find a free port.")
+        .port();
+
+    let application = Application::build(configurations)
+        .await
+        .expect("Failed to build application.");
+    let address = format!("http://127.0.0.1:{}", application.port());
+    tokio::spawn(application.run_until_stopped());
+
+    address
+}
+
+// Wait for the application to warm up, as an orchestrator would before routing
+// traffic to it
+async fn wait_until_ready(address: &str) {
+    let client = reqwest::Client::new();
+    for _ in 0..100 {
+        let response = client
+            .get(format!("{}/health_check/ready", address))
+            .send()
+            .await;
+        if matches!(response, Ok(response) if response.status().is_success()) {
+            return;
+        }
+        actix_rt::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("The application didn't get ready in time.");
+}
+
+// Encryption at rest with a single key
+pub fn encryption_configurations() -> EncryptionConfigurations {
+    EncryptionConfigurations {
+        current_key_id: "2021-04".into(),
+        keys: vec![("2021-04".to_string(), base64::encode([1u8; 32]))]
+            .into_iter()
+            .collect(),
+        blind_index_key: base64::encode([2u8; 32]),
+    }
+}
+
+// A valid subscriber, always the same one
+pub fn new_subscriber() -> NewSubscriber {
+    NewSubscriber {
+        email: SubscriberEmail::parse("nick_bourbaki@gmail.com".into()).unwrap(),
+        name: SubscriberName::parse("Nicolas Bourbaki".into()).unwrap(),
+        custom_fields: CustomFields::default(),
+        attribution: Attribution::default(),
+        source: None,
+    }
+}
+
+// Before each test we
+// (i) create a new logical database with a unique name and
+// (ii) run database migration on it.
+//
+// This is required to avoid using the same database connection for
+// all the test. That is, we need to isolate the test to be able able
+// to run it in a determistic way.
+pub async fn configure_database(config: &DatabaseConfigurations) -> PgPool {
+    // Create database
+    let mut connection = connect_to_postgres(config).await;
+    connection
+        .execute(&*format!(r#"CREATE DATABASE "{}";"#, config.database_name))
+        .await
+        .expect("Failed to create database.");
+
+    // Migrate database
+    let connection_pool = PgPool::connect_with(config.with_db())
+        .await
+        .expect("Failed to connect to Postgres.");
+    sqlx::migrate!("./migrations") // Same macro used by sqlx-cli when executing sqlx migrate run
+        .run(&connection_pool)
+        .await
+        .expect("Failed to migrate the database.");
+
+    connection_pool
+}
+
+// A freshly started Postgres container accepts TCP connections a few seconds
+// before being able to serve them, so we retry for a while.
+async fn connect_to_postgres(config: &DatabaseConfigurations) -> PgConnection {
+    let mut attempts = 0;
+    loop {
+        match PgConnection::connect_with(&config.without_db()).await {
+            Ok(connection) => return connection,
+            Err(_) if attempts < 30 => {
+                attempts += 1;
+                actix_rt::time::sleep(Duration::from_secs(1)).await;
+            }
+            Err(e) => panic!("Failed to connect to Postgres: {}", e),
+        }
+    }
+}
+
+fn is_reachable(host: &str, port: u16) -> bool {
+    (host, port)
+        .to_socket_addrs()
+        .map(|mut addresses| {
+            addresses
+                .any(|address| TcpStream::connect_timeout(&address, Duration::from_secs(1)).is_ok())
+        })
+        .unwrap_or(false)
+}
+
+// Start a Postgres container with Docker, mirroring scripts/init_db.sh, and return
+// the port it's published on. A container left running by a previous test run is
+// reused, so we don't pay for the startup every time.
+fn start_postgres_container(config: &DatabaseConfigurations) -> u16 {
+    let running = docker(&[
+        "ps",
+        "--filter",
+        &format!("label={}", POSTGRES_CONTAINER_LABEL),
+        "--format",
+        "{{.ID}}",
+    ]);
+    let id = match running.lines().next() {
+        Some(id) => id.to_owned(),
+        None => docker(&[
+            "run",
+            "--detach",
+            "--rm",
+            "--label",
+            POSTGRES_CONTAINER_LABEL,
+            "--env",
+            &format!("POSTGRES_USER={}", config.username),
+            "--env",
+            &format!("POSTGRES_PASSWORD={}", config.password),
+            // Let Docker pick a free port on the host
+            "--publish",
+            "127.0.0.1::5432",
+            "postgres",
+            "postgres",
+            "-N",
+            "1000",
+        ])
+        .trim()
+        .to_owned(),
+    };
+
+    // `docker port` prints the published addresses, e. g. `127.0.0.1:49153`
+    docker(&["port", &id, "5432/tcp"])
+        .lines()
+        .next()
+        .and_then(|address| address.rsplit(':').next())
+        .and_then(|port| port.trim().parse().ok())
+        .expect("Failed to read the port of the Postgres container.")
+}
+
+fn docker(args: &[&str]) -> String {
+    let output = Command::new("docker")
+        .args(args)
+        .output()
+        .expect("The configured database is unreachable and Docker is not available to start one.");
+    assert!(
+        output.status.success(),
+        "`docker {}` failed: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).expect("Docker's output is not UTF-8.")
+}
@@ -0,0 +1,148 @@
+//! Load-test scenarios, driving many concurrent requests against a spawned app to
+//! validate the tuning of the server, the connection pool and load shedding.
+//!
+//! They're too slow to be part of the regular test suite, so they're ignored by
+//! default. Run them in release mode to get meaningful numbers:
+//!
+//! ```sh
+//! cargo test --release --test load -- --ignored --nocapture
+//! ```
+//!
+//! `LOAD_TEST_REQUESTS` and `LOAD_TEST_CONCURRENCY` control the total number of
+//! requests and how many of them are in flight at any given time.
+mod helpers;
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use helpers::spawn_app;
+
+fn env_or(name: &str, default: usize) -> usize {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Latency and status codes of every request of a scenario.
+#[derive(Default)]
+struct Report {
+    latencies: Vec<Duration>,
+    statuses: BTreeMap<u16, usize>,
+    failures: usize,
+}
+
+impl Report {
+    fn merge(&mut self, other: Report) {
+        self.latencies.extend(other.latencies);
+        for (status, count) in other.statuses {
+            *self.statuses.entry(status).or_default() += count;
+        }
+        self.failures += other.failures;
+    }
+
+    /// Nearest-rank percentile of the latencies. They must be sorted.
+    fn percentile(&self, p: f64) -> Duration {
+        let rank = (p / 100.0 * self.latencies.len() as f64).ceil() as usize;
+        self.latencies[rank.saturating_sub(1)]
+    }
+
+    fn print(&mut self, scenario: &str, elapsed: Duration) {
+        self.latencies.sort();
+        println!("{}", scenario);
+        println!(
+            "  {} requests in {:.2?} ({:.0} req/s)",
+            self.latencies.len(),
+            elapsed,
+            self.latencies.len() as f64 / elapsed.as_secs_f64()
+        );
+        println!(
+            "  statuses: {:?}, transport failures: {}",
+            self.statuses, self.failures
+        );
+        if !self.latencies.is_empty() {
+            println!(
+                "  latency p50: {:.2?}, p90: {:.2?}, p99: {:.2?}, max: {:.2?}",
+                self.percentile(50.0),
+                self.percentile(90.0),
+                self.percentile(99.0),
+                self.latencies[self.latencies.len() - 1]
+            );
+        }
+    }
+}
+
+#[actix_rt::test]
+#[ignore]
+async fn concurrent_subscribes() {
+    // Arrange
+    let requests = env_or("LOAD_TEST_REQUESTS", 1000);
+    let concurrency = env_or("LOAD_TEST_CONCURRENCY", 100);
+    let test_app = spawn_app().await;
+    let client = reqwest::Client::new();
+    let next_request = Arc::new(AtomicUsize::new(0));
+
+    // Act
+    // Each worker keeps sending requests until the whole budget is used up, so that
+    // there are always `concurrency` requests in flight
+    let started = Instant::now();
+    let workers: Vec<_> = (0..concurrency)
+        .map(|_| {
+            let client = client.clone();
+            let address = test_app.address.clone();
+            let next_request = next_request.clone();
+            tokio::spawn(async move {
+                let mut report = Report::default();
+                loop {
+                    let i = next_request.fetch_add(1, Ordering::Relaxed);
+                    if i >= requests {
+                        break report;
+                    }
+                    // Emails are unique: every subscriber needs its own
+                    let body = format!("name=load%20test&email=load_test_{}%40example.com", i);
+                    let sent = Instant::now();
+                    let response = client
+                        .post(format!("{}/subscriptions", address))
+                        .header("Content-Type", "application/x-www-form-urlencoded")
+                        .body(body)
+                        .send()
+                        .await;
+                    match response {
+                        Ok(response) => {
+                            report.latencies.push(sent.elapsed());
+                            *report
+                                .statuses
+                                .entry(response.status().as_u16())
+                                .or_default() += 1;
+                        }
+                        Err(_) => report.failures += 1,
+                    }
+                }
+            })
+        })
+        .collect();
+
+    let mut report = Report::default();
+    for worker in workers {
+        report.merge(worker.await.expect("Load test worker panicked."));
+    }
+    report.print("POST /subscriptions", started.elapsed());
+
+    // Assert
+    // Shedding load (503) or running out of time (504) is expected under stress,
+    // anything else means that something broke
+    assert_eq!(report.failures, 0);
+    for status in report.statuses.keys() {
+        assert!(
+            [200, 503, 504].contains(status),
+            "Unexpected status code {}",
+            status
+        );
+    }
+}
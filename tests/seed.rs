@@ -0,0 +1,34 @@
+mod helpers;
+
+use helpers::spawn_app;
+use zero2prod::seed::{seed_subscribers, SEED};
+
+#[actix_rt::test]
+async fn seeding_is_deterministic() {
+    // Arrange
+    let first_app = spawn_app().await;
+    let second_app = spawn_app().await;
+
+    // Act
+    for test_app in [&first_app, &second_app] {
+        seed_subscribers(&test_app.db_pool, 20, SEED, None)
+            .await
+            .expect("Failed to seed subscribers.");
+    }
+
+    // Assert
+    let mut seeded = Vec::new();
+    for test_app in [&first_app, &second_app] {
+        let rows = sqlx::query!("SELECT email, name, utm_source FROM subscriptions ORDER BY email")
+            .fetch_all(&test_app.db_pool)
+            .await
+            .expect("Failed to fetch seeded subscriptions");
+        let rows: Vec<_> = rows
+            .into_iter()
+            .map(|row| (row.email, row.name, row.utm_source))
+            .collect();
+        seeded.push(rows);
+    }
+    assert_eq!(seeded[0].len(), 20);
+    assert_eq!(seeded[0], seeded[1]);
+}
@@ -0,0 +1,26 @@
+mod helpers;
+
+use helpers::{new_subscriber, spawn_app};
+use zero2prod::routes::insert_subscriber;
+use zero2prod::telemetry::redact_database_error;
+
+#[actix_rt::test]
+async fn database_errors_are_logged_without_subscribers_data() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let mut transaction = test_app.db_pool.begin().await.unwrap();
+    insert_subscriber(&mut transaction, &new_subscriber(), None)
+        .await
+        .expect("Failed to insert subscriber.");
+
+    // Act
+    let error = insert_subscriber(&mut transaction, &new_subscriber(), None)
+        .await
+        .expect_err("Duplicated subscribers must be rejected.");
+
+    // Assert
+    let logged = redact_database_error(&error);
+    assert!(logged.contains("23505"));
+    assert!(logged.contains("subscriptions_email_key"));
+    assert!(!logged.contains("bourbaki"));
+}